@@ -1,9 +1,18 @@
 use bstr::ByteSlice;
 use bstr_parse::*;
+use itertools::Itertools;
 use regex::bytes::Regex;
-use std::{collections::HashMap, fs::File, io::Read};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+    fs::File,
+    io::Read,
+    iter, process,
+};
 
 type Out = usize;
+type Rule<'a> = (&'a [u8], HashMap<&'a [u8], usize>);
+type Rules<'a> = HashMap<&'a [u8], HashMap<&'a [u8], usize>>;
 
 fn read_input() -> Vec<u8> {
     let mut out = Vec::new();
@@ -13,12 +22,12 @@ fn read_input() -> Vec<u8> {
     out
 }
 
-fn parse_input<'a>(input: &'a [u8]) -> HashMap<&'a [u8], HashMap<&'a [u8], usize>> {
+fn parse_rules<'a>(input: &'a [u8]) -> Vec<Rule<'a>> {
     let re = Regex::new(r"(?P<count>\d+) (?P<color>.+?) bags?").unwrap();
 
-    input.lines().fold(
-        HashMap::new(),
-        |mut m: HashMap<&'a [u8], HashMap<&'a [u8], usize>>, line: &'a [u8]| {
+    input
+        .lines()
+        .map(|line: &'a [u8]| {
             let mut split = line.split_str(" bags contain ");
             let key = split.next().unwrap();
             let value =
@@ -31,43 +40,142 @@ fn parse_input<'a>(input: &'a [u8]) -> HashMap<&'a [u8], HashMap<&'a [u8], usize
                         acc.insert(key, value);
                         acc
                     });
-            m.insert(key, value);
-            m
-        },
-    )
+            (key, value)
+        })
+        .collect_vec()
 }
 
-fn count_shiny_gold_bags<'a>(
-    data: &'a HashMap<&'a [u8], HashMap<&'a [u8], usize>>,
-    key: &'a [u8],
-    seen: &mut HashMap<&'a [u8], usize>,
-) -> usize {
-    let bag_content = data.get(key).unwrap();
-    let shiny_gold = b"shiny gold";
+fn parse_input<'a>(input: &'a [u8]) -> Rules<'a> {
+    parse_rules(input).into_iter().collect()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RuleError<'a> {
+    Cycle(Vec<&'a [u8]>),
+    Undefined {
+        color: &'a [u8],
+        container: &'a [u8],
+    },
+    Duplicate(&'a [u8]),
+}
 
-    if let Some(amount) = bag_content.get(&shiny_gold[..]) {
-        seen.insert(key, *amount);
-        return *amount;
+impl fmt::Display for RuleError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::Cycle(path) => write!(
+                f,
+                "cycle: {}",
+                path.iter().map(|c| c.as_bstr()).join(" -> ")
+            ),
+            RuleError::Undefined { color, container } => write!(
+                f,
+                "undefined color {} in rule for {}",
+                color.as_bstr(),
+                container.as_bstr()
+            ),
+            RuleError::Duplicate(color) => write!(f, "duplicate rule for {}", color.as_bstr()),
+        }
     }
+}
 
-    let amount = bag_content
+fn find_cycles<'a>(
+    data: &Rules<'a>,
+    color: &'a [u8],
+    stack: &mut Vec<&'a [u8]>,
+    done: &mut HashSet<&'a [u8]>,
+    cycles: &mut Vec<Vec<&'a [u8]>>,
+) {
+    if let Some(start) = stack.iter().position(|c| *c == color) {
+        cycles.push(
+            stack[start..]
+                .iter()
+                .copied()
+                .chain(iter::once(color))
+                .collect(),
+        );
+        return;
+    }
+    if done.contains(color) {
+        return;
+    }
+
+    stack.push(color);
+    for inner in data.get(color).into_iter().flat_map(HashMap::keys).sorted() {
+        find_cycles(data, inner, stack, done, cycles);
+    }
+    stack.pop();
+    done.insert(color);
+}
+
+fn validate<'a>(rules: &[Rule<'a>]) -> Vec<RuleError<'a>> {
+    let mut defined = HashSet::new();
+    let mut errors = rules
         .iter()
-        .map(|(key, value)| {
-            if let Some(amount) = seen.get(key) {
-                value * amount
-            } else {
-                value * count_shiny_gold_bags(data, key, seen)
+        .filter(|(color, _)| !defined.insert(*color))
+        .map(|(color, _)| RuleError::Duplicate(color))
+        .collect_vec();
+
+    errors.extend(rules.iter().flat_map(|(container, content)| {
+        content
+            .keys()
+            .filter(|color| !defined.contains(*color))
+            .sorted()
+            .map(move |color| RuleError::Undefined { color, container })
+    }));
+
+    let data: Rules = rules.iter().cloned().collect();
+    let mut cycles = Vec::new();
+    let mut done = HashSet::new();
+    for color in data.keys().sorted() {
+        find_cycles(&data, color, &mut Vec::new(), &mut done, &mut cycles);
+    }
+    errors.extend(cycles.into_iter().map(RuleError::Cycle));
+
+    errors
+}
+
+fn containers_of<'a>(data: &'a Rules<'a>, color: &[u8]) -> Vec<&'a [u8]> {
+    let reverse = data.iter().fold(
+        HashMap::new(),
+        |mut m: HashMap<&'a [u8], Vec<&'a [u8]>>, (outer, content)| {
+            content
+                .keys()
+                .for_each(|inner| m.entry(*inner).or_default().push(*outer));
+            m
+        },
+    );
+
+    let mut seen = HashSet::new();
+    let mut q = vec![color];
+
+    while let Some(current) = q.pop() {
+        for outer in reverse.get(current).into_iter().flatten() {
+            if seen.insert(*outer) {
+                q.push(outer);
             }
-        })
-        .max()
-        .unwrap_or(0);
+        }
+    }
 
-    seen.insert(key, amount);
-    amount
+    seen.into_iter().sorted().collect_vec()
+}
+
+fn contents_of<'a>(data: &'a Rules<'a>, color: &[u8]) -> Vec<&'a [u8]> {
+    let mut seen = HashSet::new();
+    let mut q = vec![color];
+
+    while let Some(current) = q.pop() {
+        for inner in data.get(current).into_iter().flat_map(HashMap::keys) {
+            if seen.insert(*inner) {
+                q.push(inner);
+            }
+        }
+    }
+
+    seen.into_iter().sorted().collect_vec()
 }
 
 fn count_bags_in<'a>(
-    data: &'a HashMap<&'a [u8], HashMap<&'a [u8], usize>>,
+    data: &'a Rules<'a>,
     key: &'a [u8],
     seen: &mut HashMap<&'a [u8], usize>,
 ) -> usize {
@@ -89,23 +197,124 @@ fn count_bags_in<'a>(
     amount
 }
 
-fn part_1<'a>(data: &'a HashMap<&'a [u8], HashMap<&'a [u8], usize>>) -> Out {
-    let mut shiny = HashMap::new();
-    data.iter()
-        .filter(|(key, _map)| count_shiny_gold_bags(data, key, &mut shiny) > 0)
-        .count()
+// `None` if there is no rule for `color`.
+fn count_bags_inside<'a>(data: &'a Rules<'a>, color: &'a [u8]) -> Option<usize> {
+    data.contains_key(color)
+        .then(|| count_bags_in(data, color, &mut HashMap::new()) - 1)
+}
+
+fn containment_paths<'a>(data: &'a Rules<'a>, from: &'a [u8], to: &[u8]) -> Vec<Vec<&'a [u8]>> {
+    fn walk<'a>(
+        data: &'a Rules<'a>,
+        to: &[u8],
+        path: &mut Vec<&'a [u8]>,
+        paths: &mut Vec<Vec<&'a [u8]>>,
+    ) {
+        let current = *path.last().unwrap();
+        if current == to {
+            paths.push(path.clone());
+            return;
+        }
+
+        for inner in data.get(current).into_iter().flat_map(HashMap::keys) {
+            if !path.contains(inner) {
+                path.push(inner);
+                walk(data, to, path, paths);
+                path.pop();
+            }
+        }
+    }
+
+    let mut paths = Vec::new();
+    walk(data, to, &mut vec![from], &mut paths);
+    paths.sort_unstable();
+    paths
+}
+
+enum Subgraph<'a> {
+    All,
+    From(&'a [u8]),
+    To(&'a [u8]),
+}
+
+fn to_dot<'a>(data: &'a Rules<'a>, subgraph: Subgraph) -> String {
+    let nodes: HashSet<&[u8]> = match subgraph {
+        Subgraph::All => data
+            .iter()
+            .flat_map(|(outer, content)| iter::once(*outer).chain(content.keys().copied()))
+            .collect(),
+        Subgraph::From(color) => iter::once(color).chain(contents_of(data, color)).collect(),
+        Subgraph::To(color) => iter::once(color)
+            .chain(containers_of(data, color))
+            .collect(),
+    };
+
+    let edges = data
+        .iter()
+        .filter(|(outer, _)| nodes.contains(*outer))
+        .flat_map(|(outer, content)| content.iter().map(move |(inner, n)| (*outer, *inner, n)))
+        .filter(|(_, inner, _)| nodes.contains(inner))
+        .sorted()
+        .map(|(outer, inner, n)| {
+            format!(
+                "    {:?} -> {:?} [label=\"{}\"];\n",
+                outer.as_bstr(),
+                inner.as_bstr(),
+                n
+            )
+        });
+
+    iter::once("digraph bags {\n".to_owned())
+        .chain(
+            nodes
+                .iter()
+                .sorted()
+                .map(|c| format!("    {:?};\n", c.as_bstr())),
+        )
+        .chain(edges)
+        .chain(iter::once("}\n".to_owned()))
+        .collect()
+}
+
+fn part_1<'a>(data: &'a Rules<'a>) -> Out {
+    containers_of(data, b"shiny gold").len()
 }
 
-fn part_2<'a>(data: &'a HashMap<&'a [u8], HashMap<&'a [u8], usize>>) -> Out {
-    let mut shiny = HashMap::new();
-    count_bags_in(data, b"shiny gold", &mut shiny) - 1
+fn part_2<'a>(data: &'a Rules<'a>) -> Out {
+    count_bags_inside(data, b"shiny gold").unwrap()
 }
+
 fn main() {
     let input = read_input();
+
+    let errors = validate(&parse_rules(&input));
+    if !errors.is_empty() {
+        errors.iter().for_each(|e| eprintln!("{}", e));
+        process::exit(1);
+    }
+
     let parsed = parse_input(&input);
+    let args = env::args().skip(1).collect_vec();
 
-    println!("Part 1: {}", part_1(&parsed));
-    println!("Part 2: {}", part_2(&parsed));
+    match args.iter().map(String::as_str).collect_vec()[..] {
+        ["containers", color] => containers_of(&parsed, color.as_bytes())
+            .iter()
+            .for_each(|c| println!("{}", c.as_bstr())),
+        ["contents", color] => match count_bags_inside(&parsed, color.as_bytes()) {
+            Some(n) => println!("{}", n),
+            None => println!("no rule for {} bags", color),
+        },
+        ["paths", from, to] => containment_paths(&parsed, from.as_bytes(), to.as_bytes())
+            .iter()
+            .for_each(|p| println!("{}", p.iter().map(|c| c.as_bstr()).join(" -> "))),
+        ["dot"] => print!("{}", to_dot(&parsed, Subgraph::All)),
+        ["dot", "from", color] => print!("{}", to_dot(&parsed, Subgraph::From(color.as_bytes()))),
+        ["dot", "to", color] => print!("{}", to_dot(&parsed, Subgraph::To(color.as_bytes()))),
+        _ => {
+            println!("Part 1: {}", part_1(&parsed));
+            println!("Part 2: {}", part_2(&parsed));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -141,4 +350,81 @@ dark violet bags contain no other bags.";
         let parsed = parse_input(TEST_INPUT_P2.as_bytes());
         assert_eq!(part_2(&parsed), 126);
     }
+
+    #[test]
+    fn test_queries() {
+        let parsed = parse_input(TEST_INPUT_P1.as_bytes());
+        assert_eq!(
+            containers_of(&parsed, b"bright white"),
+            vec![&b"dark orange"[..], b"light red"]
+        );
+        assert_eq!(count_bags_inside(&parsed, b"shiny gold"), Some(32));
+        assert_eq!(count_bags_inside(&parsed, b"faded blue"), Some(0));
+        assert_eq!(count_bags_inside(&parsed, b"no such"), None);
+        assert_eq!(
+            containment_paths(&parsed, b"light red", b"shiny gold"),
+            vec![
+                vec![&b"light red"[..], b"bright white", b"shiny gold"],
+                vec![&b"light red"[..], b"muted yellow", b"shiny gold"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let parsed = parse_input(TEST_INPUT_P1.as_bytes());
+        assert_eq!(
+            to_dot(&parsed, Subgraph::From(b"dark olive")),
+            r#"digraph bags {
+    "dark olive";
+    "dotted black";
+    "faded blue";
+    "dark olive" -> "dotted black" [label="4"];
+    "dark olive" -> "faded blue" [label="3"];
+}
+"#
+        );
+        assert_eq!(
+            to_dot(&parsed, Subgraph::To(b"bright white")),
+            r#"digraph bags {
+    "bright white";
+    "dark orange";
+    "light red";
+    "dark orange" -> "bright white" [label="3"];
+    "light red" -> "bright white" [label="1"];
+}
+"#
+        );
+        assert_eq!(to_dot(&parsed, Subgraph::All).matches(" -> ").count(), 13);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&parse_rules(TEST_INPUT_P1.as_bytes())).is_empty());
+
+        let rules = parse_rules(
+            "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+shiny gold bags contain 3 light red bags, 1 dark olive bag.
+muted yellow bags contain no other bags.
+muted yellow bags contain no other bags."
+                .as_bytes(),
+        );
+        assert_eq!(
+            validate(&rules),
+            vec![
+                RuleError::Duplicate(b"muted yellow"),
+                RuleError::Undefined {
+                    color: b"dark olive",
+                    container: b"shiny gold"
+                },
+                RuleError::Cycle(vec![
+                    b"bright white",
+                    b"shiny gold",
+                    b"light red",
+                    b"bright white"
+                ]),
+            ]
+        );
+    }
 }
@@ -1,107 +1,644 @@
-#![feature(destructuring_assignment)]
-
 use bstr::ByteSlice;
 use bstr_parse::*;
 use itertools::Itertools;
-use std::{collections::VecDeque, fs::File, io::Read, mem::replace};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    process,
+};
 
 type Parsed = Vec<Instruction>;
 type Out = i32;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Instruction {
     Jmp(i32),
     Acc(i32),
     Nop(i32),
-    End,
+    Mul(i32),
+    Jz(i32),
+    Jnz(i32),
 }
 
-fn read_input() -> Vec<u8> {
-    let mut out = Vec::new();
-    let mut f = File::open("input/day-08.txt").unwrap();
+const MNEMONICS: &[&str] = &["jmp", "acc", "nop", "mul", "jz", "jnz"];
 
-    f.read_to_end(&mut out).unwrap();
-    out
+impl Instruction {
+    fn new(mnemonic: &str, n: i32) -> Option<Self> {
+        match mnemonic {
+            "jmp" => Some(Instruction::Jmp(n)),
+            "acc" => Some(Instruction::Acc(n)),
+            "nop" => Some(Instruction::Nop(n)),
+            "mul" => Some(Instruction::Mul(n)),
+            "jz" => Some(Instruction::Jz(n)),
+            "jnz" => Some(Instruction::Jnz(n)),
+            _ => None,
+        }
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Jmp(_) => "jmp",
+            Instruction::Acc(_) => "acc",
+            Instruction::Nop(_) => "nop",
+            Instruction::Mul(_) => "mul",
+            Instruction::Jz(_) => "jz",
+            Instruction::Jnz(_) => "jnz",
+        }
+    }
+
+    fn operand(&self) -> i32 {
+        match self {
+            Instruction::Jmp(n)
+            | Instruction::Acc(n)
+            | Instruction::Nop(n)
+            | Instruction::Mul(n)
+            | Instruction::Jz(n)
+            | Instruction::Jnz(n) => *n,
+        }
+    }
+
+    fn is_conditional(&self) -> bool {
+        matches!(self, Instruction::Jz(_) | Instruction::Jnz(_))
+    }
 }
 
-fn parse_input(input: &[u8]) -> Parsed {
-    input
-        .lines()
-        .map(|i| {
-            let mut iter = i.split_str(" ");
-            let cmd = iter.next().unwrap();
-            let value = iter.next().unwrap().parse().unwrap();
-            match cmd {
-                b"jmp" => Instruction::Jmp(value),
-                b"acc" => Instruction::Acc(value),
-                b"nop" => Instruction::Nop(value),
-                _ => panic!(),
-            }
-        })
-        .collect_vec()
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:+}", self.mnemonic(), self.operand())
+    }
 }
 
-fn part_1(mut data: Parsed) -> Out {
-    let mut acc = 0;
-    let mut pc: i32 = 0;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Halt {
+    Terminated,
+    InfiniteLoop,
+    OutOfBounds(i64),
+    // The accumulator would overflow.
+    Overflow,
+    // With conditional jumps a loop may never repeat a state, so runs are
+    // bounded instead.
+    StepLimit,
+}
 
-    loop {
-        match replace(&mut data[pc as usize], Instruction::End) {
-            Instruction::Acc(n) => acc += n,
-            Instruction::Jmp(n) => {
-                pc += n;
-                continue;
+const MAX_STEPS: usize = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Step {
+    pc: usize,
+    instruction: Instruction,
+    acc: i32,
+}
+
+struct Machine {
+    program: Vec<Instruction>,
+    pc: usize,
+    acc: i32,
+    steps: usize,
+    max_steps: usize,
+    visited: Vec<u64>,
+    seen_states: HashSet<(usize, i32)>,
+    conditional: bool,
+    halted: Option<Halt>,
+    trace: Option<Vec<Step>>,
+}
+
+impl Machine {
+    fn new(program: Vec<Instruction>) -> Self {
+        Machine {
+            visited: vec![0; program.len() / 64 + 1],
+            seen_states: HashSet::new(),
+            conditional: program.iter().any(Instruction::is_conditional),
+            program,
+            pc: 0,
+            acc: 0,
+            steps: 0,
+            max_steps: MAX_STEPS,
+            halted: None,
+            trace: None,
+        }
+    }
+
+    fn with_step_limit(self, max_steps: usize) -> Self {
+        Machine { max_steps, ..self }
+    }
+
+    fn traced(program: Vec<Instruction>) -> Self {
+        Machine {
+            trace: Some(Vec::new()),
+            ..Machine::new(program)
+        }
+    }
+
+    fn reset(&mut self) {
+        self.visited.iter_mut().for_each(|w| *w = 0);
+        self.seen_states.clear();
+        self.pc = 0;
+        self.acc = 0;
+        self.steps = 0;
+        self.halted = None;
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+    }
+
+    fn patch(&mut self, addr: usize, instruction: Instruction) {
+        self.program[addr] = instruction;
+        self.conditional = self.program.iter().any(Instruction::is_conditional);
+    }
+
+    // Without conditional jumps the path only depends on pc, so a bitset is
+    // enough; otherwise the same pc may legitimately be visited with another acc.
+    fn mark_visited(&mut self) -> bool {
+        if self.conditional {
+            self.seen_states.insert((self.pc, self.acc))
+        } else {
+            let (word, bit) = (self.pc / 64, 1 << (self.pc % 64));
+            let first = self.visited[word] & bit == 0;
+            self.visited[word] |= bit;
+            first
+        }
+    }
+
+    fn step(&mut self) -> Option<Halt> {
+        if self.halted.is_some() {
+            return self.halted;
+        }
+        if self.pc == self.program.len() {
+            self.halted = Some(Halt::Terminated);
+            return self.halted;
+        }
+        if self.steps == self.max_steps {
+            self.halted = Some(Halt::StepLimit);
+            return self.halted;
+        }
+        if !self.mark_visited() {
+            self.halted = Some(Halt::InfiniteLoop);
+            return self.halted;
+        }
+
+        let instruction = self.program[self.pc];
+        let acc = match instruction {
+            Instruction::Acc(n) => self.acc.checked_add(n),
+            Instruction::Mul(n) => self.acc.checked_mul(n),
+            _ => Some(self.acc),
+        };
+        match acc {
+            Some(acc) => self.acc = acc,
+            None => {
+                self.halted = Some(Halt::Overflow);
+                return self.halted;
             }
-            Instruction::Nop(_) => {}
-            Instruction::End => return acc,
+        }
+
+        let next = match instruction {
+            Instruction::Jz(n) if self.acc == 0 => self.pc as i64 + n as i64,
+            Instruction::Jnz(n) if self.acc != 0 => self.pc as i64 + n as i64,
+            Instruction::Jmp(n) => self.pc as i64 + n as i64,
+            _ => self.pc as i64 + 1,
         };
 
-        pc += 1;
+        self.steps += 1;
+        if let Some(trace) = &mut self.trace {
+            trace.push(Step {
+                pc: self.pc,
+                instruction,
+                acc: self.acc,
+            });
+        }
+
+        if next < 0 || next > self.program.len() as i64 {
+            self.halted = Some(Halt::OutOfBounds(next));
+        } else {
+            self.pc = next as usize;
+        }
+        self.halted
+    }
+
+    fn run(&mut self) -> Halt {
+        loop {
+            if let Some(halt) = self.step() {
+                return halt;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Breakpoint {
+    Address(usize),
+    Kind(&'static str),
+}
+
+impl Breakpoint {
+    fn parse(target: &str) -> Option<Self> {
+        match MNEMONICS.iter().find(|m| **m == target) {
+            Some(kind) => Some(Breakpoint::Kind(kind)),
+            None => target.parse().ok().map(Breakpoint::Address),
+        }
+    }
+
+    fn matches(&self, pc: usize, instruction: &Instruction) -> bool {
+        match self {
+            Breakpoint::Address(addr) => *addr == pc,
+            Breakpoint::Kind(kind) => *kind == instruction.mnemonic(),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(addr) => write!(f, "{}", addr),
+            Breakpoint::Kind(kind) => write!(f, "{}", kind),
+        }
     }
 }
 
-fn part_2(mut data: Parsed) -> Out {
-    let mut acc = 0;
-    let mut pc: i32 = 0;
-    let mut q = VecDeque::new();
+struct Debugger {
+    machine: Machine,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl Debugger {
+    fn new(program: Vec<Instruction>) -> Self {
+        Debugger {
+            machine: Machine::traced(program),
+            breakpoints: Vec::new(),
+        }
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        let pc = self.machine.pc;
+        self.machine.halted.is_none()
+            && self.machine.program.get(pc).is_some_and(|instruction| {
+                self.breakpoints.iter().any(|b| b.matches(pc, instruction))
+            })
+    }
 
-    let mut did_travel_back_in_time = false;
+    fn report(&self, out: &mut impl Write) -> io::Result<()> {
+        match self.machine.halted {
+            Some(halt) => writeln!(out, "halted: {:?} acc={}", halt, self.machine.acc),
+            None => writeln!(
+                out,
+                "{:>4}  {}",
+                self.machine.pc, self.machine.program[self.machine.pc]
+            ),
+        }
+    }
+
+    fn history(&self, n: usize, out: &mut impl Write) -> io::Result<()> {
+        let trace = self.machine.trace.as_deref().unwrap_or_default();
+        for step in &trace[trace.len().saturating_sub(n)..] {
+            writeln!(
+                out,
+                "{:>4}  {}  acc={}",
+                step.pc, step.instruction, step.acc
+            )?;
+        }
+        Ok(())
+    }
 
-    while pc < data.len() as i32 {
-        match replace(&mut data[pc as usize], Instruction::End) {
-            Instruction::Acc(n) => acc += n,
-            Instruction::Jmp(n) => {
-                if !did_travel_back_in_time {
-                    q.push_back((pc + 1, acc));
+    fn execute(&mut self, command: &str, out: &mut impl Write) -> io::Result<bool> {
+        match command.split_whitespace().collect_vec()[..] {
+            [] => {}
+            ["break", target] => match Breakpoint::parse(target) {
+                Some(b) => {
+                    self.breakpoints.push(b);
+                    writeln!(out, "breakpoint set: {}", b)?;
                 }
-                pc += n;
-                continue;
+                None => writeln!(out, "invalid breakpoint: {}", target)?,
+            },
+            ["delete", target] => {
+                let before = self.breakpoints.len();
+                self.breakpoints
+                    .retain(|b| Some(*b) != Breakpoint::parse(target));
+                if self.breakpoints.len() == before {
+                    writeln!(out, "no breakpoint: {}", target)?;
+                }
+            }
+            ["step"] => {
+                self.machine.step();
+                self.report(out)?;
+            }
+            ["step", n] => match n.parse() {
+                Ok(n) => {
+                    for _ in 0..n {
+                        if self.machine.step().is_some() {
+                            break;
+                        }
+                    }
+                    self.report(out)?;
+                }
+                Err(_) => writeln!(out, "invalid count: {}", n)?,
+            },
+            ["continue"] => {
+                while self.machine.step().is_none() && !self.at_breakpoint() {}
+                if self.at_breakpoint() {
+                    write!(out, "breakpoint hit: ")?;
+                }
+                self.report(out)?;
             }
-            Instruction::Nop(0) => {}
-            Instruction::Nop(n) => {
-                if !did_travel_back_in_time {
-                    q.push_back((pc + n, acc));
+            ["print"] => writeln!(out, "pc={} acc={}", self.machine.pc, self.machine.acc)?,
+            ["print", "pc"] => writeln!(out, "{}", self.machine.pc)?,
+            ["print", "acc"] => writeln!(out, "{}", self.machine.acc)?,
+            ["history"] => self.history(usize::MAX, out)?,
+            ["history", n] => match n.parse() {
+                Ok(n) => self.history(n, out)?,
+                Err(_) => writeln!(out, "invalid count: {}", n)?,
+            },
+            ["patch", addr, cmd, value] => {
+                let instruction = parse_instruction(format!("{} {}", cmd, value).as_bytes());
+                match (addr.parse::<usize>(), instruction) {
+                    (Ok(addr), Some(instruction)) if addr < self.machine.program.len() => {
+                        self.machine.patch(addr, instruction);
+                        writeln!(out, "{:>4}  {}", addr, instruction)?;
+                    }
+                    _ => writeln!(out, "invalid patch: {}", command)?,
                 }
             }
-            Instruction::End => {
-                (pc, acc) = q.pop_back().unwrap();
+            ["restart"] => {
+                self.machine.reset();
+                self.report(out)?;
+            }
+            ["quit"] => return Ok(false),
+            _ => writeln!(out, "unknown command: {}", command)?,
+        }
+        Ok(true)
+    }
+
+    fn run(&mut self, input: impl io::BufRead, mut out: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.execute(line?.trim(), &mut out)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Repair {
+    addr: usize,
+    patched: Instruction,
+    acc: i32,
+}
 
-                did_travel_back_in_time = true;
+fn successors(pc: usize, instruction: Instruction) -> Vec<i64> {
+    match instruction {
+        Instruction::Jmp(n) => vec![pc as i64 + n as i64],
+        Instruction::Jz(n) | Instruction::Jnz(n) => vec![pc as i64 + 1, pc as i64 + n as i64],
+        _ => vec![pc as i64 + 1],
+    }
+}
 
-                continue;
+fn terminating(program: &[Instruction]) -> Vec<bool> {
+    let len = program.len();
+    let mut predecessors = vec![Vec::new(); len + 1];
+    for (pc, instruction) in program.iter().enumerate() {
+        for next in successors(pc, *instruction) {
+            if (0..=len as i64).contains(&next) {
+                predecessors[next as usize].push(pc);
             }
-        };
+        }
+    }
 
-        pc += 1;
+    let mut reaches_end = vec![false; len + 1];
+    reaches_end[len] = true;
+    let mut q = vec![len];
+    while let Some(pc) = q.pop() {
+        for &prev in &predecessors[pc] {
+            if !reaches_end[prev] {
+                reaches_end[prev] = true;
+                q.push(prev);
+            }
+        }
     }
-    acc
+    reaches_end
 }
+
+fn find_repairs(program: &[Instruction]) -> Vec<Repair> {
+    let reaches_end = terminating(program);
+
+    let mut machine = Machine::traced(program.to_vec());
+    machine.run();
+
+    // With conditional jumps an address may be traced several times.
+    machine
+        .trace
+        .unwrap()
+        .iter()
+        .map(|step| step.pc)
+        .unique()
+        .filter_map(|pc| {
+            let patched = match program[pc] {
+                Instruction::Jmp(n) => Instruction::Nop(n),
+                Instruction::Nop(n) => Instruction::Jmp(n),
+                _ => return None,
+            };
+            let next = successors(pc, patched)[0];
+            if !(0..=program.len() as i64).contains(&next) || !reaches_end[next as usize] {
+                return None;
+            }
+
+            let mut program = program.to_vec();
+            program[pc] = patched;
+            let mut machine = Machine::new(program);
+            (machine.run() == Halt::Terminated).then_some(Repair {
+                addr: pc,
+                patched,
+                acc: machine.acc,
+            })
+        })
+        .sorted_by_key(|r| r.addr)
+        .collect_vec()
+}
+
+fn read_input() -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut f = File::open("input/day-08.txt").unwrap();
+
+    f.read_to_end(&mut out).unwrap();
+    out
+}
+
+fn parse_instruction(line: &[u8]) -> Option<Instruction> {
+    let mut iter = line.split_str(" ");
+    let cmd = iter.next()?.to_str().ok()?;
+    let value = iter.next()?.parse().ok()?;
+    Instruction::new(cmd, value)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum AsmError {
+    UnknownMnemonic(usize, String),
+    InvalidOperand(usize, String),
+    UndefinedLabel(usize, String),
+    DuplicateLabel(usize, String),
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic(line, m) => {
+                write!(f, "line {}: unknown mnemonic {:?}", line, m)
+            }
+            AsmError::InvalidOperand(line, o) => {
+                write!(f, "line {}: invalid operand {:?}", line, o)
+            }
+            AsmError::UndefinedLabel(line, l) => {
+                write!(f, "line {}: undefined label {:?}", line, l)
+            }
+            AsmError::DuplicateLabel(line, l) => {
+                write!(f, "line {}: duplicate label {:?}", line, l)
+            }
+        }
+    }
+}
+
+fn assemble(source: &[u8]) -> Result<Parsed, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+
+    for (n, line) in source.lines().enumerate() {
+        let line = line.to_str_lossy();
+        let mut line = line.split(['#', ';']).next().unwrap().trim();
+
+        if let Some((label, rest)) = line.split_once(':') {
+            if labels
+                .insert(label.trim().to_owned(), statements.len())
+                .is_some()
+            {
+                return Err(AsmError::DuplicateLabel(n + 1, label.trim().to_owned()));
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operand) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if !MNEMONICS.contains(&mnemonic) {
+            return Err(AsmError::UnknownMnemonic(n + 1, mnemonic.to_owned()));
+        }
+        statements.push((n + 1, mnemonic.to_owned(), operand.trim().to_owned()));
+    }
+
+    statements
+        .iter()
+        .enumerate()
+        .map(|(pc, (line, mnemonic, operand))| {
+            let is_label =
+                !operand.is_empty() && operand.chars().all(|c| c.is_alphanumeric() || c == '_');
+            let value = match (operand.as_bytes().parse::<i32>(), labels.get(operand)) {
+                (Ok(value), _) => value,
+                (Err(_), Some(&target)) => target as i32 - pc as i32,
+                (Err(_), None) if is_label => {
+                    return Err(AsmError::UndefinedLabel(*line, operand.clone()))
+                }
+                (Err(_), None) => return Err(AsmError::InvalidOperand(*line, operand.clone())),
+            };
+            Ok(Instruction::new(mnemonic, value).unwrap())
+        })
+        .collect()
+}
+
+fn disassemble(program: &[Instruction]) -> String {
+    program
+        .iter()
+        .enumerate()
+        .map(|(pc, instruction)| match instruction {
+            Instruction::Jmp(n) | Instruction::Jz(n) | Instruction::Jnz(n) => format!(
+                "{:>4}  {:<10}; -> {}\n",
+                pc,
+                instruction.to_string(),
+                pc as i64 + *n as i64
+            ),
+            _ => format!("{:>4}  {}\n", pc, instruction),
+        })
+        .collect()
+}
+
+fn parse_input(input: &[u8]) -> Parsed {
+    assemble(input).unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn part_1(data: &[Instruction]) -> Out {
+    let mut machine = Machine::new(data.to_vec());
+    machine.run();
+    machine.acc
+}
+
+fn part_2(data: &[Instruction]) -> Out {
+    find_repairs(data).first().unwrap().acc
+}
+
+const ASM_USAGE: &str = "usage: day-08 asm <file> [max_steps]";
+
 fn main() {
     let input = read_input();
     let parsed = parse_input(&input);
+    let args = env::args().skip(1).collect_vec();
+
+    match args.iter().map(String::as_str).collect_vec()[..] {
+        ["trace"] => {
+            let mut machine = Machine::traced(parsed);
+            let halt = machine.run();
+            for step in machine.trace.unwrap() {
+                println!("{:>4}  {:?}  acc={}", step.pc, step.instruction, step.acc);
+            }
+            println!("{:?} after {} steps", halt, machine.steps);
+        }
+        ["repairs"] => {
+            for r in find_repairs(&parsed) {
+                println!(
+                    "{:>4}  {} -> {}  acc={}",
+                    r.addr, parsed[r.addr], r.patched, r.acc
+                );
+            }
+        }
+        ["disasm"] => print!("{}", disassemble(&parsed)),
+        ["asm", path, ref max_steps @ ..] if max_steps.len() <= 1 => {
+            let max_steps = match max_steps.first().map_or(Ok(MAX_STEPS), |n| n.parse()) {
+                Ok(max_steps) => max_steps,
+                Err(_) => {
+                    eprintln!("{}", ASM_USAGE);
+                    process::exit(1);
+                }
+            };
+            let source = match fs::read(path) {
+                Ok(source) => source,
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    process::exit(1);
+                }
+            };
 
-    println!("Part 1: {}", part_1(parsed.clone()));
-    println!("Part 2: {}", part_2(parsed));
+            match assemble(&source) {
+                Ok(program) => {
+                    print!("{}", disassemble(&program));
+                    let mut machine = Machine::new(program).with_step_limit(max_steps);
+                    println!("{:?} acc={}", machine.run(), machine.acc);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        ["asm", ..] => {
+            eprintln!("{}", ASM_USAGE);
+            process::exit(1);
+        }
+        ["debug"] => Debugger::new(parsed)
+            .run(io::stdin().lock(), io::stdout())
+            .unwrap(),
+        _ => {
+            println!("Part 1: {}", part_1(&parsed));
+            println!("Part 2: {}", part_2(&parsed));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -121,12 +658,172 @@ acc +6";
     #[test]
     fn test_part_1() {
         let parsed = parse_input(TEST_INPUT.as_bytes());
-        assert_eq!(part_1(parsed), 5);
+        assert_eq!(part_1(&parsed), 5);
     }
 
     #[test]
     fn test_part_2() {
         let parsed = parse_input(TEST_INPUT.as_bytes());
-        assert_eq!(part_2(parsed), 8);
+        assert_eq!(part_2(&parsed), 8);
+    }
+
+    #[test]
+    fn test_machine() {
+        let mut machine = Machine::traced(parse_input(TEST_INPUT.as_bytes()));
+        assert_eq!(machine.run(), Halt::InfiniteLoop);
+        assert_eq!((machine.pc, machine.acc, machine.steps), (1, 5, 7));
+        assert_eq!(
+            machine.trace.unwrap().iter().map(|s| s.pc).collect_vec(),
+            vec![0, 1, 2, 6, 7, 3, 4]
+        );
+
+        let mut machine = Machine::new(parse_input(b"nop +0\nacc +1"));
+        assert_eq!(machine.step(), None);
+        assert_eq!(machine.run(), Halt::Terminated);
+        assert_eq!(machine.acc, 1);
+
+        let mut machine = Machine::new(parse_input(b"acc +1\njmp -2"));
+        assert_eq!(machine.run(), Halt::OutOfBounds(-1));
+        assert_eq!((machine.pc, machine.steps), (1, 2));
+    }
+
+    #[test]
+    fn test_find_repairs() {
+        let parsed = parse_input(TEST_INPUT.as_bytes());
+        assert_eq!(
+            find_repairs(&parsed),
+            vec![Repair {
+                addr: 7,
+                patched: Instruction::Nop(-4),
+                acc: 8
+            }]
+        );
+
+        let parsed = parse_input(b"nop +3\njmp +0\nacc +1");
+        assert_eq!(
+            find_repairs(&parsed),
+            vec![
+                Repair {
+                    addr: 0,
+                    patched: Instruction::Jmp(3),
+                    acc: 0
+                },
+                Repair {
+                    addr: 1,
+                    patched: Instruction::Nop(0),
+                    acc: 1
+                },
+            ]
+        );
+
+        let parsed = parse_input(b"acc +2\nnop +3\nacc -1\njnz -2");
+        assert_eq!(
+            find_repairs(&parsed),
+            vec![Repair {
+                addr: 1,
+                patched: Instruction::Jmp(3),
+                acc: 2
+            }]
+        );
+    }
+
+    const TEST_ASM: &str = "# count down to zero
+start:  acc +3      ; counter
+loop:   acc -1
+        jnz loop
+        jz end
+        mul -2
+end:
+";
+
+    #[test]
+    fn test_assemble() {
+        let program = assemble(TEST_ASM.as_bytes()).unwrap();
+        assert_eq!(
+            disassemble(&program),
+            "   0  acc +3
+   1  acc -1
+   2  jnz -1    ; -> 1
+   3  jz +2     ; -> 5
+   4  mul -2
+"
+        );
+
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run(), Halt::Terminated);
+        assert_eq!((machine.acc, machine.steps), (0, 8));
+
+        let mut machine = Machine::new(assemble(b"acc +3\nmul -2\njnz +0").unwrap());
+        assert_eq!(machine.run(), Halt::InfiniteLoop);
+        assert_eq!(machine.acc, -6);
+
+        let mut machine = Machine::new(assemble(b"acc +2\nmul 1073741824").unwrap());
+        assert_eq!(machine.run(), Halt::Overflow);
+        assert_eq!((machine.pc, machine.acc), (1, 2));
+
+        let program = assemble(b"acc +1\njnz -1").unwrap();
+        let mut machine = Machine::new(program.clone()).with_step_limit(10_000);
+        assert_eq!(machine.run(), Halt::StepLimit);
+        assert_eq!((machine.steps, machine.seen_states.len()), (10_000, 10_000));
+        assert_eq!(Machine::new(program).run(), Halt::StepLimit);
+
+        assert_eq!(
+            assemble(b"acc +1\nfoo +1"),
+            Err(AsmError::UnknownMnemonic(2, "foo".to_owned()))
+        );
+        assert_eq!(
+            assemble(b"jmp nowhere"),
+            Err(AsmError::UndefinedLabel(1, "nowhere".to_owned()))
+        );
+        assert_eq!(
+            assemble(b"acc +x1"),
+            Err(AsmError::InvalidOperand(1, "+x1".to_owned()))
+        );
+        assert_eq!(
+            assemble(b"a: nop +0\na:"),
+            Err(AsmError::DuplicateLabel(2, "a".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_debugger() {
+        let script = "break jmp
+continue
+print
+continue
+history 2
+patch 7 nop -4
+delete jmp
+step 3
+continue
+restart
+break 6
+continue
+print acc
+quit
+print
+";
+        let mut out = Vec::new();
+        Debugger::new(parse_input(TEST_INPUT.as_bytes()))
+            .run(script.as_bytes(), &mut out)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "breakpoint set: jmp
+breakpoint hit:    2  jmp +4
+pc=2 acc=1
+breakpoint hit:    7  jmp -4
+   2  jmp +4  acc=1
+   6  acc +1  acc=2
+   7  nop -4
+halted: Terminated acc=8
+halted: Terminated acc=8
+   0  nop +0
+breakpoint set: 6
+breakpoint hit:    6  acc +1
+1
+"
+        );
     }
 }
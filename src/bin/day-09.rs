@@ -1,9 +1,15 @@
-#![feature(destructuring_assignment)]
-
 use bstr::ByteSlice;
 use bstr_parse::*;
 use itertools::Itertools;
-use std::{collections::HashSet, fs::File, io::Read};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    env,
+    fs::File,
+    io::{self, Read},
+    iter,
+    num::NonZeroUsize,
+    process,
+};
 
 fn read_input() -> Vec<u8> {
     let mut out = Vec::new();
@@ -17,67 +23,215 @@ fn parse_input(input: &[u8]) -> Vec<u64> {
     input.lines().map(|i| i.parse().unwrap()).collect_vec()
 }
 
-fn part_1(data: &[u64], preamble: usize) -> (u64, usize) {
-    let mut s = data[0..preamble - 1].iter().collect::<HashSet<_>>();
-
-    for i in 0..=data.len() - preamble {
-        s.insert(&data[i + preamble]);
+struct Validator {
+    window: Vec<u64>,
+    head: usize,
+    sums: HashMap<u64, usize>,
+    preamble: usize,
+}
 
-        let current = data[i + preamble];
+impl Validator {
+    fn new(preamble: NonZeroUsize) -> Self {
+        Validator {
+            window: Vec::with_capacity(preamble.get()),
+            head: 0,
+            sums: HashMap::new(),
+            preamble: preamble.get(),
+        }
+    }
 
-        if !data[i..i + preamble]
+    fn pair_sums(&self, n: u64) -> impl Iterator<Item = u64> + '_ {
+        self.window
             .iter()
-            .filter(|j| *j * 2 != current)
-            .filter_map(|j| current.checked_sub(*j))
-            .any(|j| s.contains(&j))
-        {
-            return (current, i + preamble);
-        }
-        s.remove(&data[i]);
+            .filter(move |j| **j != n)
+            .filter_map(move |j| j.checked_add(n))
     }
-    unreachable!()
-}
 
-fn part_2(data: &[u64], tosum: u64) -> u64 {
-    let mut running_sum = 0;
-    let mut j = 0;
-    for i in 0..(data.len() - 1) {
-        while running_sum < tosum {
-            running_sum += data[j];
-            j += 1;
+    // Returns whether `n` is the sum of two distinct values of the window,
+    // numbers of the preamble being valid by definition.
+    fn push(&mut self, n: u64) -> bool {
+        if self.window.len() < self.preamble {
+            let sums = self.pair_sums(n).collect_vec();
+            sums.into_iter()
+                .for_each(|sum| *self.sums.entry(sum).or_default() += 1);
+            self.window.push(n);
+            return true;
         }
 
-        if running_sum == tosum {
-            let (min, max) = data[i..j - 1].iter().minmax().into_option().unwrap();
-            return min + max;
+        let valid = self.sums.contains_key(&n);
+
+        let removed = self.pair_sums(self.window[self.head]).collect_vec();
+        self.window[self.head] = n;
+        let added = self.pair_sums(n).collect_vec();
+        self.head = (self.head + 1) % self.preamble;
+
+        for sum in removed {
+            if let Entry::Occupied(mut e) = self.sums.entry(sum) {
+                *e.get_mut() -= 1;
+                if *e.get() == 0 {
+                    e.remove();
+                }
+            }
+        }
+        for sum in added {
+            *self.sums.entry(sum).or_default() += 1;
         }
 
-        // remove first item
-        running_sum -= data[i];
+        valid
+    }
+}
+
+fn invalid_numbers(
+    data: &[u64],
+    preamble: NonZeroUsize,
+) -> impl Iterator<Item = (u64, usize)> + '_ {
+    validate_stream(data.iter().copied(), preamble)
+}
+
+fn validate_stream(
+    numbers: impl Iterator<Item = u64>,
+    preamble: NonZeroUsize,
+) -> impl Iterator<Item = (u64, usize)> {
+    let mut validator = Validator::new(preamble);
+    numbers
+        .enumerate()
+        .filter(move |(_, n)| !validator.push(*n))
+        .map(|(i, n)| (n, i))
+}
+
+fn read_numbers(reader: impl io::BufRead) -> impl Iterator<Item = u64> {
+    reader
+        .lines()
+        .map(|l| l.unwrap())
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.trim().parse().unwrap())
+}
+
+fn part_1(data: &[u64], preamble: NonZeroUsize) -> Option<(u64, usize)> {
+    invalid_numbers(data, preamble).next()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SumRange {
+    start: usize,
+    end: usize,
+    min: i128,
+    max: i128,
+}
 
-        // revert last additions if necessary
-        while running_sum > tosum {
-            j -= 1;
-            running_sum -= data[j];
+impl SumRange {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+// Every range of at least two numbers summing to `target`, with `end` exclusive.
+// Sums are over i128 so any u64 input fits.
+fn contiguous_ranges(data: &[i128], target: i128) -> Vec<SumRange> {
+    let prefix = iter::once(0)
+        .chain(data.iter().scan(0, |sum, n| {
+            *sum += n;
+            Some(*sum)
+        }))
+        .collect_vec();
+
+    let mut starts: HashMap<i128, Vec<usize>> = HashMap::new();
+    let mut ranges = Vec::new();
+    for end in 2..prefix.len() {
+        starts.entry(prefix[end - 2]).or_default().push(end - 2);
+
+        for &start in starts.get(&(prefix[end] - target)).into_iter().flatten() {
+            let (min, max) = data[start..end].iter().minmax().into_option().unwrap();
+            ranges.push(SumRange {
+                start,
+                end,
+                min: *min,
+                max: *max,
+            });
         }
     }
 
-    unreachable!()
+    ranges.sort_unstable_by_key(|r| (r.start, r.end));
+    ranges
+}
+
+fn part_2(data: &[u64], tosum: u64) -> Option<u128> {
+    let data = data.iter().map(|n| *n as i128).collect_vec();
+
+    contiguous_ranges(&data, tosum as i128)
+        .first()
+        .map(|r| (r.min + r.max) as u128)
 }
+
 fn main() {
+    let args = env::args().skip(1).collect_vec();
+
+    let parsed_args = match args.iter().map(String::as_str).collect_vec()[..] {
+        [] => NonZeroUsize::new(25).map(|p| (p, "")),
+        [preamble] => preamble.parse().ok().map(|p| (p, "")),
+        [preamble, mode @ ("all" | "stream" | "ranges")] => {
+            preamble.parse().ok().map(|p| (p, mode))
+        }
+        _ => None,
+    };
+    let (preamble, mode) = match parsed_args {
+        Some(parsed_args) => parsed_args,
+        None => {
+            eprintln!("usage: day-09 [preamble > 0] [all|stream|ranges]");
+            process::exit(1);
+        }
+    };
+
+    if mode == "stream" {
+        for (n, index) in validate_stream(read_numbers(io::stdin().lock()), preamble) {
+            println!("{:>5}  {}", index, n);
+        }
+        return;
+    }
+
     let input = read_input();
     let parsed = parse_input(&input);
 
-    let (p1_r, p1_index) = part_1(&parsed, 25);
+    if mode == "all" {
+        for (n, index) in invalid_numbers(&parsed, preamble) {
+            println!("{:>5}  {}", index, n);
+        }
+        return;
+    }
 
-    println!("Part 1: {}", p1_r);
-    println!("Part 2: {}", part_2(&parsed[..p1_index], p1_r));
+    match part_1(&parsed, preamble) {
+        Some((p1_r, _)) if mode == "ranges" => {
+            let data = parsed.iter().map(|n| *n as i128).collect_vec();
+            for r in contiguous_ranges(&data, p1_r as i128) {
+                println!(
+                    "{:>5}..{:<5} len={} min={} max={}",
+                    r.start,
+                    r.end,
+                    r.len(),
+                    r.min,
+                    r.max
+                );
+            }
+        }
+        Some((p1_r, p1_index)) => {
+            println!("Part 1: {}", p1_r);
+            match part_2(&parsed[..p1_index], p1_r) {
+                Some(p2_r) => println!("Part 2: {}", p2_r),
+                None => println!("Part 2: no contiguous range sums to {}", p1_r),
+            }
+        }
+        None => println!("Part 1: every number is valid"),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn nz(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
     const TEST_INPUT: &str = "35
 20
 15
@@ -102,12 +256,83 @@ mod tests {
     #[test]
     fn test_part_1() {
         let parsed = parse_input(TEST_INPUT.as_bytes());
-        assert_eq!(part_1(&parsed, 5).0, 127);
+        assert_eq!(part_1(&parsed, nz(5)), Some((127, 14)));
+        assert_eq!(part_1(&parsed[..14], nz(5)), None);
+        assert_eq!(part_1(&[1, 2, 3], nz(1)), Some((2, 1)));
+        assert!("0".parse::<NonZeroUsize>().is_err());
+    }
+
+    #[test]
+    fn test_invalid_numbers() {
+        let parsed = parse_input(TEST_INPUT.as_bytes());
+        assert_eq!(
+            invalid_numbers(&parsed, nz(5)).collect_vec(),
+            vec![(127, 14), (57, 19)]
+        );
+        assert_eq!(invalid_numbers(&parsed, nz(3)).count(), 16);
+    }
+
+    #[test]
+    fn test_validate_stream() {
+        let numbers = read_numbers(TEST_INPUT.as_bytes());
+        assert_eq!(
+            validate_stream(numbers, nz(5)).collect_vec(),
+            vec![(127, 14), (57, 19)]
+        );
+
+        let mut validator = Validator::new(nz(2));
+        assert!(validator.push(1) && validator.push(2));
+        assert!(validator.push(3) && validator.push(5));
+        assert!(!validator.push(10));
+        assert_eq!(validator.window.len(), 2);
+        assert_eq!(validator.sums.keys().collect_vec(), vec![&15]);
+
+        let numbers = (0..200u64).map(|i| i * 7 % 23 + 1).collect_vec();
+        let naive = (5..numbers.len())
+            .filter(|&i| {
+                !numbers[i - 5..i]
+                    .iter()
+                    .tuple_combinations()
+                    .any(|(a, b)| a != b && a + b == numbers[i])
+            })
+            .map(|i| (numbers[i], i))
+            .collect_vec();
+        assert_eq!(invalid_numbers(&numbers, nz(5)).collect_vec(), naive);
     }
 
     #[test]
     fn test_part_2() {
         let parsed = parse_input(TEST_INPUT.as_bytes());
-        assert_eq!(part_2(&parsed, 127), 62);
+        assert_eq!(part_2(&parsed, 127), Some(62));
+
+        let big = [1 << 62, 1 << 62, 1 << 62];
+        assert_eq!(part_1(&big, nz(2)), Some((1 << 62, 2)));
+        assert_eq!(part_2(&big[..2], 1 << 62), None);
+        assert_eq!(part_2(&big, 3 << 62), Some(1 << 63));
+        assert_eq!(part_2(&[u64::MAX - 1, 1], u64::MAX), Some(u64::MAX as u128));
+    }
+
+    #[test]
+    fn test_contiguous_ranges() {
+        let parsed = parse_input(TEST_INPUT.as_bytes())
+            .iter()
+            .map(|n| *n as i128)
+            .collect_vec();
+        assert_eq!(
+            contiguous_ranges(&parsed, 127),
+            vec![SumRange {
+                start: 2,
+                end: 6,
+                min: 15,
+                max: 47
+            }]
+        );
+
+        let ranges = contiguous_ranges(&[3, -2, 4, -5, 5, 2, -2], 5);
+        assert_eq!(
+            ranges.iter().map(|r| (r.start, r.end)).collect_vec(),
+            vec![(0, 3), (0, 5), (0, 7), (4, 7)]
+        );
+        assert_eq!((ranges[2].len(), ranges[2].min, ranges[2].max), (7, -5, 5));
     }
 }
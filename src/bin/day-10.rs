@@ -1,9 +1,9 @@
-#![feature(destructuring_assignment)]
-
 use bstr::ByteSlice;
 use bstr_parse::*;
 use itertools::Itertools;
-use std::{fs::File, io::Read, iter};
+use std::{
+    collections::BTreeMap, env, fmt, fs::File, io::Read, iter, num::NonZeroU64, ops, process,
+};
 
 fn read_input() -> Vec<u8> {
     let mut out = Vec::new();
@@ -23,44 +23,406 @@ fn parse_input(input: &[u8]) -> Vec<usize> {
     out
 }
 
-fn part_1(data: &[usize]) -> usize {
-    let (diff_1, diff_3) =
+// Little-endian base 10^9 digits, only what counting arrangements needs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+const BIG_BASE: u32 = 1_000_000_000;
+
+impl From<u64> for BigUint {
+    fn from(mut n: u64) -> Self {
+        let mut digits = vec![(n % BIG_BASE as u64) as u32];
+        n /= BIG_BASE as u64;
+        while n > 0 {
+            digits.push((n % BIG_BASE as u64) as u32);
+            n /= BIG_BASE as u64;
+        }
+        BigUint(digits)
+    }
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut carry = 0;
+    let mut digits = (0..a.len().max(b.len()))
+        .map(|i| {
+            let sum = a.get(i).unwrap_or(&0) + b.get(i).unwrap_or(&0) + carry;
+            carry = sum / BIG_BASE;
+            sum % BIG_BASE
+        })
+        .collect_vec();
+    if carry > 0 {
+        digits.push(carry);
+    }
+    digits
+}
+
+impl ops::Add for &BigUint {
+    type Output = BigUint;
+
+    fn add(self, other: &BigUint) -> BigUint {
+        BigUint(add_digits(&self.0, &other.0))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut digits = self.0.iter().rev();
+        write!(f, "{}", digits.next().unwrap())?;
+        digits.try_for_each(|d| write!(f, "{:09}", d))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Arrangements {
+    Small(u64),
+    Big(BigUint),
+}
+
+impl fmt::Display for Arrangements {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arrangements::Small(n) => write!(f, "{}", n),
+            Arrangements::Big(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AdapterChain {
+    max_gap: usize,
+    device_offset: usize,
+}
+
+const PUZZLE_CHAIN: AdapterChain = AdapterChain {
+    max_gap: 3,
+    device_offset: 3,
+};
+
+impl AdapterChain {
+    fn device(&self, data: &[usize]) -> usize {
+        data.last().unwrap() + self.device_offset
+    }
+
+    // Gaps of the chain using every adapter, or `None` if one is too wide.
+    fn histogram(&self, data: &[usize]) -> Option<BTreeMap<usize, usize>> {
         data.iter()
+            .chain(iter::once(&self.device(data)))
             .tuple_windows()
             .map(|(a, b)| b - a)
-            .fold((0, 1), |(diff_1, diff_3), diff| match diff {
-                3 => (diff_1, diff_3 + 1),
-                1 => (diff_1 + 1, diff_3),
-                _ => (diff_1, diff_3),
-            });
+            .try_fold(BTreeMap::new(), |mut histogram, gap| {
+                if gap > self.max_gap {
+                    return None;
+                }
+                *histogram.entry(gap).or_default() += 1;
+                Some(histogram)
+            })
+    }
 
-    diff_1 * diff_3
-}
+    fn count_with<T: Clone>(
+        &self,
+        data: &[usize],
+        zero: T,
+        one: T,
+        add: impl Fn(&T, &T) -> T,
+    ) -> T {
+        let device = self.device(data);
+        let mut ways = vec![one];
+
+        for i in 1..data.len() {
+            let n = (0..i)
+                .rev()
+                .take_while(|&j| data[i] - data[j] <= self.max_gap)
+                .fold(zero.clone(), |acc, j| add(&acc, &ways[j]));
+            ways.push(n);
+        }
+
+        (0..data.len())
+            .rev()
+            .take_while(|&j| device - data[j] <= self.max_gap)
+            .fold(zero, |acc, j| add(&acc, &ways[j]))
+    }
+
+    fn checked_arrangements(&self, data: &[usize]) -> Option<u64> {
+        self.count_with(data, Some(0), Some(1), |a, b| (*a)?.checked_add((*b)?))
+    }
+
+    fn big_arrangements(&self, data: &[usize]) -> BigUint {
+        self.count_with(data, BigUint::from(0), BigUint::from(1), |a, b| a + b)
+    }
 
-fn part_2(data: &[usize]) -> usize {
-    data.iter()
-        .tuple_windows()
-        .map(|(a, b)| b - a)
-        .fold((1, 0, 0), |(diff_1, diff_2, diff_3), diff| match diff {
-            1 => (diff_1 + diff_2 + diff_3, diff_1, diff_2),
-            2 => (diff_1 + diff_2, 0, diff_1),
-            3 => (diff_1, 0, 0),
-            _ => unreachable!(),
+    fn arrangements_mod(&self, data: &[usize], modulus: NonZeroU64) -> u64 {
+        let modulus = modulus.get();
+        self.count_with(data, 0, 1 % modulus, |a, b| {
+            ((*a as u128 + *b as u128) % modulus as u128) as u64
         })
-        .0
+    }
+
+    fn arrangements(&self, data: &[usize]) -> Arrangements {
+        match self.checked_arrangements(data) {
+            Some(n) => Arrangements::Small(n),
+            None => Arrangements::Big(self.big_arrangements(data)),
+        }
+    }
+
+    fn can_finish(&self, data: &[usize]) -> Vec<bool> {
+        let device = self.device(data);
+        let mut can_finish = vec![false; data.len()];
+
+        for i in (0..data.len()).rev() {
+            can_finish[i] = device - data[i] <= self.max_gap
+                || (i + 1..data.len())
+                    .take_while(|&j| data[j] - data[i] <= self.max_gap)
+                    .any(|j| can_finish[j]);
+        }
+        can_finish
+    }
+
+    fn chains<'a>(&self, data: &'a [usize]) -> Chains<'a> {
+        let can_finish = self.can_finish(data);
+        Chains {
+            data,
+            device: self.device(data),
+            max_gap: self.max_gap,
+            stack: if can_finish[0] { vec![(0, 0)] } else { vec![] },
+            can_finish,
+        }
+    }
+
+    fn extreme_chain(&self, data: &[usize], longest: bool) -> Option<Vec<usize>> {
+        let device = self.device(data);
+        let pick = |candidates: &mut dyn Iterator<Item = (usize, usize)>| {
+            if longest {
+                candidates.max_by_key(|(len, _)| *len)
+            } else {
+                candidates.min_by_key(|(len, _)| *len)
+            }
+        };
+
+        // (adapters in the best chain ending here, previous adapter)
+        let mut best: Vec<Option<(usize, usize)>> = vec![Some((1, 0))];
+        for i in 1..data.len() {
+            best.push(pick(
+                &mut (0..i)
+                    .rev()
+                    .take_while(|&j| data[i] - data[j] <= self.max_gap)
+                    .filter_map(|j| best[j].map(|(len, _)| (len + 1, j))),
+            ));
+        }
+
+        let (_, mut last) = pick(
+            &mut (0..data.len())
+                .rev()
+                .take_while(|&j| device - data[j] <= self.max_gap)
+                .filter_map(|j| best[j].map(|(len, _)| (len, j))),
+        )?;
+
+        let mut chain = vec![data[last]];
+        while last != 0 {
+            last = best[last].unwrap().1;
+            chain.push(data[last]);
+        }
+        chain.reverse();
+        Some(chain)
+    }
+
+    fn shortest_chain(&self, data: &[usize]) -> Option<Vec<usize>> {
+        self.extreme_chain(data, false)
+    }
+
+    fn longest_chain(&self, data: &[usize]) -> Option<Vec<usize>> {
+        self.extreme_chain(data, true)
+    }
+
+    // Uniform over every arrangement, `None` if there is none or if they are
+    // too many to be counted on 128 bits.
+    fn sample(&self, data: &[usize], rng: &mut Rng) -> Option<Vec<usize>> {
+        let device = self.device(data);
+        let successors =
+            |i: usize| (i + 1..data.len()).take_while(move |&j| data[j] - data[i] <= self.max_gap);
+
+        let mut ways = vec![Some(0u128); data.len()];
+        for i in (0..data.len()).rev() {
+            let end = (device - data[i] <= self.max_gap) as u128;
+            ways[i] = successors(i).try_fold(end, |acc, j| acc.checked_add(ways[j]?));
+        }
+
+        let mut chain = vec![data[0]];
+        let mut i = 0;
+        let mut r = rng.below(ways[0].filter(|n| *n > 0)?);
+        loop {
+            if device - data[i] <= self.max_gap {
+                if r == 0 {
+                    return Some(chain);
+                }
+                r -= 1;
+            }
+            i = successors(i)
+                .find(|&j| {
+                    let w = ways[j].unwrap();
+                    if r < w {
+                        true
+                    } else {
+                        r -= w;
+                        false
+                    }
+                })
+                .unwrap();
+            chain.push(data[i]);
+        }
+    }
+}
+
+// Lazily walks every arrangement in depth-first order, each one starting at the
+// outlet and not including the device.
+struct Chains<'a> {
+    data: &'a [usize],
+    device: usize,
+    max_gap: usize,
+    can_finish: Vec<bool>,
+    // (adapter index, next candidate), a candidate equal to the adapter index
+    // meaning the chain stops there.
+    stack: Vec<(usize, usize)>,
+}
+
+impl Iterator for Chains<'_> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        while let Some((i, next)) = self.stack.last_mut() {
+            let (i, candidate) = (*i, *next);
+            *next += 1;
+
+            if candidate == i {
+                if self.device - self.data[i] <= self.max_gap {
+                    return Some(self.stack.iter().map(|(i, _)| self.data[*i]).collect());
+                }
+            } else if candidate < self.data.len()
+                && self.data[candidate] - self.data[i] <= self.max_gap
+            {
+                if self.can_finish[candidate] {
+                    self.stack.push((candidate, candidate));
+                }
+            } else {
+                self.stack.pop();
+            }
+        }
+        None
+    }
+}
+
+// splitmix64, enough to get reproducible samples without pulling a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u128) -> u128 {
+        let zone = u128::MAX - u128::MAX % n;
+        loop {
+            let r = (self.next_u64() as u128) << 64 | self.next_u64() as u128;
+            if r < zone {
+                return r % n;
+            }
+        }
+    }
+}
+
+fn part_1(data: &[usize]) -> usize {
+    let histogram = PUZZLE_CHAIN.histogram(data).unwrap();
+    histogram.get(&1).unwrap_or(&0) * histogram.get(&3).unwrap_or(&0)
+}
+
+fn part_2(data: &[usize]) -> Arrangements {
+    PUZZLE_CHAIN.arrangements(data)
 }
 
 fn main() {
     let input = read_input();
     let parsed = parse_input(&input);
+    let args = env::args().skip(1).collect_vec();
+
+    let print_chain = |chain: Option<Vec<usize>>| match chain {
+        Some(chain) => println!("{}", chain.iter().join(",")),
+        None => println!("no arrangement"),
+    };
+    match args.iter().map(String::as_str).collect_vec()[..] {
+        ["chains", n] => {
+            for chain in PUZZLE_CHAIN.chains(&parsed).take(n.parse().unwrap()) {
+                print_chain(Some(chain));
+            }
+            return;
+        }
+        ["shortest"] => return print_chain(PUZZLE_CHAIN.shortest_chain(&parsed)),
+        ["longest"] => return print_chain(PUZZLE_CHAIN.longest_chain(&parsed)),
+        ["sample", seed] => {
+            let mut rng = Rng(seed.parse().unwrap());
+            return print_chain(PUZZLE_CHAIN.sample(&parsed, &mut rng));
+        }
+        _ => {}
+    }
+
+    let (chain, modulus) = match args.iter().map(|a| a.parse().unwrap()).collect_vec()[..] {
+        [] => {
+            println!("Part 1: {}", part_1(&parsed));
+            println!("Part 2: {}", part_2(&parsed));
+            return;
+        }
+        [max_gap] => (
+            AdapterChain {
+                max_gap,
+                device_offset: max_gap,
+            },
+            None,
+        ),
+        [max_gap, device_offset] => (
+            AdapterChain {
+                max_gap,
+                device_offset,
+            },
+            None,
+        ),
+        [max_gap, device_offset, modulus] => (
+            AdapterChain {
+                max_gap,
+                device_offset,
+            },
+            match NonZeroU64::new(modulus as u64) {
+                Some(modulus) => Some(modulus),
+                None => {
+                    eprintln!("the modulus must be positive");
+                    process::exit(1);
+                }
+            },
+        ),
+        _ => panic!("usage: day-10 [max_gap [device_offset [modulus]]]"),
+    };
 
-    println!("Part 1: {}", part_1(&parsed));
-    println!("Part 2: {}", part_2(&parsed));
+    match chain.histogram(&parsed) {
+        Some(histogram) => histogram
+            .iter()
+            .for_each(|(gap, n)| println!("gap {}: {}", gap, n)),
+        None => println!("some gap is wider than {}", chain.max_gap),
+    }
+    match modulus {
+        Some(modulus) => println!(
+            "arrangements mod {}: {}",
+            modulus,
+            chain.arrangements_mod(&parsed, modulus)
+        ),
+        None => println!("arrangements: {}", chain.arrangements(&parsed)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::{HashMap, HashSet};
 
     const TEST_INPUT: &str = "28
 33
@@ -103,6 +465,104 @@ mod tests {
     #[test]
     fn test_part_2() {
         let parsed = parse_input(TEST_INPUT.as_bytes());
-        assert_eq!(part_2(&parsed), 19208);
+        assert_eq!(part_2(&parsed), Arrangements::Small(19208));
+    }
+
+    #[test]
+    fn test_adapter_chain() {
+        let parsed = parse_input(b"1\n2\n3\n5\n9");
+
+        let chain = AdapterChain {
+            max_gap: 4,
+            device_offset: 1,
+        };
+        assert_eq!(
+            chain.histogram(&parsed),
+            Some(vec![(1, 4), (2, 1), (4, 1)].into_iter().collect())
+        );
+        assert_eq!(chain.arrangements(&parsed), Arrangements::Small(7));
+
+        let chain = AdapterChain {
+            max_gap: 2,
+            device_offset: 2,
+        };
+        assert_eq!(chain.histogram(&parsed), None);
+        assert_eq!(chain.arrangements(&parsed), Arrangements::Small(0));
+    }
+
+    #[test]
+    fn test_big_arrangements() {
+        let parsed = (0..=100).collect_vec();
+
+        assert_eq!(PUZZLE_CHAIN.checked_arrangements(&parsed), None);
+        assert_eq!(
+            PUZZLE_CHAIN.arrangements(&parsed).to_string(),
+            "180396380815100901214157639"
+        );
+        assert_eq!(
+            PUZZLE_CHAIN.arrangements_mod(&parsed, NonZeroU64::new(1_000_000_007).unwrap()),
+            347873931
+        );
+        assert_eq!(
+            PUZZLE_CHAIN.arrangements_mod(&parsed, NonZeroU64::new(1).unwrap()),
+            0
+        );
+        assert_eq!(BigUint::from(1_000_000_000).to_string(), "1000000000");
+    }
+
+    #[test]
+    fn test_chains() {
+        let parsed = parse_input(b"1\n2\n3\n5\n9");
+        let chain = AdapterChain {
+            max_gap: 4,
+            device_offset: 1,
+        };
+
+        assert_eq!(
+            chain.chains(&parsed).collect_vec(),
+            vec![
+                vec![0, 1, 2, 3, 5, 9],
+                vec![0, 1, 2, 5, 9],
+                vec![0, 1, 3, 5, 9],
+                vec![0, 1, 5, 9],
+                vec![0, 2, 3, 5, 9],
+                vec![0, 2, 5, 9],
+                vec![0, 3, 5, 9],
+            ]
+        );
+        assert_eq!(chain.shortest_chain(&parsed), Some(vec![0, 3, 5, 9]));
+        assert_eq!(chain.longest_chain(&parsed), Some(vec![0, 1, 2, 3, 5, 9]));
+
+        let mut rng = Rng(7);
+        let counts = (0..7000)
+            .map(|_| chain.sample(&parsed, &mut rng).unwrap())
+            .fold(HashMap::new(), |mut counts, c| {
+                *counts.entry(c).or_insert(0) += 1;
+                counts
+            });
+        assert_eq!(counts.len(), 7);
+        assert!(counts.values().all(|n| (800..1200).contains(n)));
+
+        let parsed = parse_input(TEST_INPUT.as_bytes());
+        let all = PUZZLE_CHAIN.chains(&parsed).collect::<HashSet<_>>();
+        assert_eq!(all.len(), 19208);
+
+        let mut rng = Rng(42);
+        let samples = (0..100)
+            .map(|_| PUZZLE_CHAIN.sample(&parsed, &mut rng).unwrap())
+            .collect_vec();
+        assert!(samples.iter().all(|s| all.contains(s)));
+        assert!(samples.iter().unique().count() > 90);
+        assert_eq!(
+            samples[0],
+            PUZZLE_CHAIN.sample(&parsed, &mut Rng(42)).unwrap()
+        );
+
+        let gap = AdapterChain {
+            max_gap: 2,
+            device_offset: 2,
+        };
+        assert_eq!(gap.chains(&parse_input(b"1\n4")).next(), None);
+        assert_eq!(gap.sample(&parse_input(b"1\n4"), &mut rng), None);
     }
 }
@@ -1,10 +1,18 @@
-#![feature(destructuring_assignment)]
+#![cfg_attr(test, feature(test))]
 
 use bstr::ByteSlice;
 use itertools::{iproduct, Itertools};
-use std::{fs::File, io::Read};
+use rayon::prelude::*;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    env,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::Read,
+    iter,
+};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum Seat {
     Occupied,
     Empty,
@@ -35,134 +43,241 @@ fn parse_input(input: &[u8]) -> Vec<Vec<Seat>> {
         .collect_vec()
 }
 
-fn part_1(data: &[Vec<Seat>]) -> usize {
-    let mut changed = true;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Neighborhood {
+    Eight,
+    Four,
+    // Rows are read as axial hex coordinates.
+    Hex,
+}
 
-    let height = data.len();
-    let width = data[0].len();
+impl Neighborhood {
+    fn directions(&self) -> &'static [(i64, i64)] {
+        match self {
+            Neighborhood::Eight => &[
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ],
+            Neighborhood::Four => &[(-1, 0), (0, -1), (0, 1), (1, 0)],
+            Neighborhood::Hex => &[(-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0)],
+        }
+    }
+}
 
-    let mut last_generation;
-    let mut current_generation = data.iter().cloned().collect_vec();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SeatingRules {
+    neighborhood: Neighborhood,
+    // An occupied seat is left once it sees more occupied seats than this.
+    max_visible_occupied: usize,
+    // How many cells away a seat can be seen, `None` for the whole line of sight.
+    max_range: Option<usize>,
+}
 
-    while changed {
-        last_generation = current_generation.iter().cloned().collect_vec();
+const ADJACENT_RULES: SeatingRules = SeatingRules {
+    neighborhood: Neighborhood::Eight,
+    max_visible_occupied: 3,
+    max_range: Some(1),
+};
 
-        changed = false;
-        for (x, y) in iproduct!(0..height, 0..width) {
-            let min_x = x.saturating_sub(1);
-            let max_x = if x + 1 == height { x } else { x + 1 };
-            let min_y = y.saturating_sub(1);
-            let max_y = if y + 1 == width { y } else { y + 1 };
-
-            let mut area =
-                iproduct!(min_x..=max_x, min_y..=max_y).filter(|(a, b)| (a, b) != (&x, &y));
-
-            match current_generation[x][y] {
-                Seat::Empty => {
-                    let should_change = area.all(|(a, b)| last_generation[a][b] != Seat::Occupied);
-                    changed = changed || should_change;
-                    if should_change {
-                        current_generation[x][y] = Seat::Occupied;
-                    }
-                }
+const LINE_OF_SIGHT_RULES: SeatingRules = SeatingRules {
+    neighborhood: Neighborhood::Eight,
+    max_visible_occupied: 4,
+    max_range: None,
+};
+
+// For every cell of the flattened layout, the indices of the seats it sees,
+// stored contiguously and delimited by `offsets`.
+struct Visibility {
+    offsets: Vec<usize>,
+    neighbors: Vec<usize>,
+}
+
+impl Visibility {
+    fn new(data: &[Vec<Seat>], rules: &SeatingRules) -> Self {
+        let height = data.len() as i64;
+        let width = data[0].len() as i64;
+
+        let mut offsets = vec![0];
+        let mut neighbors = Vec::new();
 
-                Seat::Occupied => {
-                    let should_change = area
-                        .filter(|(a, b)| last_generation[*a][*b] == Seat::Occupied)
-                        .count()
-                        >= 4;
-                    changed = changed || should_change;
-                    if should_change {
-                        current_generation[x][y] = Seat::Empty;
+        for (x, y) in iproduct!(0..height, 0..width) {
+            if data[x as usize][y as usize] != Seat::Floor {
+                for (dx, dy) in rules.neighborhood.directions() {
+                    let (mut a, mut b) = (x + dx, y + dy);
+                    let mut range = 1;
+                    while (0..height).contains(&a)
+                        && (0..width).contains(&b)
+                        && rules.max_range.is_none_or(|max| range <= max)
+                    {
+                        if data[a as usize][b as usize] != Seat::Floor {
+                            neighbors.push((a * width + b) as usize);
+                            break;
+                        }
+                        a += dx;
+                        b += dy;
+                        range += 1;
                     }
                 }
-                _ => {}
             }
+            offsets.push(neighbors.len());
         }
+
+        Visibility { offsets, neighbors }
     }
 
-    current_generation
-        .iter()
-        .map(|l| l.iter().filter(|i| i == &&Seat::Occupied).count())
-        .sum()
+    fn of(&self, i: usize) -> &[usize] {
+        &self.neighbors[self.offsets[i]..self.offsets[i + 1]]
+    }
 }
 
-fn part_2(data: &[Vec<Seat>]) -> usize {
-    let mut changed = true;
+fn step(seats: &[Seat], visibility: &Visibility, max_visible_occupied: usize) -> Vec<Seat> {
+    seats
+        .par_iter()
+        .enumerate()
+        .map(|(i, seat)| {
+            let occupied = || {
+                visibility
+                    .of(i)
+                    .iter()
+                    .filter(|j| seats[**j] == Seat::Occupied)
+                    .count()
+            };
+            match seat {
+                Seat::Empty if occupied() == 0 => Seat::Occupied,
+                Seat::Occupied if occupied() > max_visible_occupied => Seat::Empty,
+                seat => *seat,
+            }
+        })
+        .collect()
+}
 
-    let height = data.len();
-    let width = data[0].len();
+const MAX_GENERATIONS: usize = 100_000;
 
-    let mut last_generation;
-    let mut current_generation = data.iter().cloned().collect_vec();
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Stable {
+        seats: Vec<Seat>,
+        generation: usize,
+    },
+    Cycle {
+        start: usize,
+        period: usize,
+        states: Vec<Vec<Seat>>,
+    },
+    Unsettled,
+}
 
-    while changed {
-        last_generation = current_generation.iter().cloned().collect_vec();
+fn simulate(data: &[Vec<Seat>], rules: &SeatingRules, max_generations: usize) -> Outcome {
+    let visibility = Visibility::new(data, rules);
+    let next = |seats: &Vec<Seat>| step(seats, &visibility, rules.max_visible_occupied);
+    let initial = data.concat();
 
-        changed = false;
-        for (x, y) in iproduct!(0..height, 0..width) {
-            let mut area = iproduct!(-1i64..=1i64, -1i64..=1i64)
-                .filter(|(a, b)| (a, b) != (&0, &0))
-                .map(|(a, b)| {
-                    (1..)
-                        .map(|n| {
-                            last_generation
-                                .get(if a < 0 {
-                                    x.checked_sub((n * -a) as usize)?
-                                } else {
-                                    x + (n * a) as usize
-                                })?
-                                .get(if b < 0 {
-                                    y.checked_sub((n * -b) as usize)?
-                                } else {
-                                    y + (n * b) as usize
-                                })
-                        })
-                        .find_map(|seat| match seat {
-                            None => Some(Seat::Floor),
-                            Some(Seat::Floor) => None,
-                            Some(s) => Some(s.clone()),
-                        })
-                        .unwrap()
-                });
-
-            match current_generation[x][y] {
-                Seat::Empty => {
-                    let should_change = area.all(|s| s != Seat::Occupied);
-                    changed = changed || should_change;
-                    if should_change {
-                        current_generation[x][y] = Seat::Occupied;
-                    }
-                }
+    // Only hashes of past generations are kept; a matching hash is confirmed,
+    // and the cycle rebuilt, by replaying from the initial layout.
+    let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut seats = initial.clone();
 
-                Seat::Occupied => {
-                    let should_change = area.filter(|s| s == &Seat::Occupied).count() >= 5;
-                    changed = changed || should_change;
-                    if should_change {
-                        current_generation[x][y] = Seat::Empty;
-                    }
-                }
-                _ => {}
+    for generation in 0..max_generations {
+        let mut hasher = DefaultHasher::new();
+        seats.hash(&mut hasher);
+        let same_hash = seen.entry(hasher.finish()).or_default();
+
+        for &start in same_hash.iter() {
+            let states = iter::successors(Some(initial.clone()), |s| Some(next(s)))
+                .skip(start)
+                .take(generation - start)
+                .collect_vec();
+            if states[0] == seats {
+                return Outcome::Cycle {
+                    start,
+                    period: generation - start,
+                    states,
+                };
             }
         }
+        same_hash.push(generation);
+
+        let following = next(&seats);
+        if following == seats {
+            return Outcome::Stable {
+                seats: following,
+                generation,
+            };
+        }
+        seats = following;
     }
 
-    current_generation
-        .iter()
-        .map(|l| l.iter().filter(|i| i == &&Seat::Occupied).count())
-        .sum()
+    Outcome::Unsettled
+}
+
+fn count_occupied(data: &[Vec<Seat>], rules: &SeatingRules) -> Option<usize> {
+    match simulate(data, rules, MAX_GENERATIONS) {
+        Outcome::Stable { seats, .. } => {
+            Some(seats.iter().filter(|s| **s == Seat::Occupied).count())
+        }
+        _ => None,
+    }
+}
+
+fn part_1(data: &[Vec<Seat>]) -> usize {
+    count_occupied(data, &ADJACENT_RULES).unwrap()
+}
+
+fn part_2(data: &[Vec<Seat>]) -> usize {
+    count_occupied(data, &LINE_OF_SIGHT_RULES).unwrap()
 }
+
 fn main() {
     let input = read_input();
     let parsed = parse_input(&input);
+    let args = env::args().skip(1).collect_vec();
+
+    let neighborhood = match args.first().map(String::as_str) {
+        None => {
+            println!("Part 1: {}", part_1(&parsed));
+            println!("Part 2: {}", part_2(&parsed));
+            return;
+        }
+        Some("eight") => Neighborhood::Eight,
+        Some("four") => Neighborhood::Four,
+        Some("hex") => Neighborhood::Hex,
+        Some(n) => panic!("unknown neighborhood {}", n),
+    };
+    let rules = SeatingRules {
+        neighborhood,
+        max_visible_occupied: args
+            .get(1)
+            .expect("usage: day-11 <eight|four|hex> <max_visible_occupied> [max_range]")
+            .parse()
+            .unwrap(),
+        max_range: args.get(2).map(|r| r.parse().unwrap()),
+    };
 
-    println!("Part 1: {}", part_1(&parsed));
-    println!("Part 2: {}", part_2(&parsed));
+    match simulate(&parsed, &rules, MAX_GENERATIONS) {
+        Outcome::Stable { seats, generation } => println!(
+            "Stable after {} generations, occupied: {}",
+            generation,
+            seats.iter().filter(|s| **s == Seat::Occupied).count()
+        ),
+        Outcome::Cycle { start, period, .. } => {
+            println!("Cycle of period {} from generation {}", period, start)
+        }
+        Outcome::Unsettled => println!("Unsettled after {} generations", MAX_GENERATIONS),
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    extern crate test;
+
     use super::*;
+    use test::Bencher;
 
     const TEST_INPUT: &str = "L.LL.LL.LL
 LLLLLLL.LL
@@ -176,6 +291,125 @@ L.LLLLLL.L
 L.LLLLL.LL
 ";
 
+    fn naive_part_1(data: &[Vec<Seat>]) -> usize {
+        let mut changed = true;
+
+        let height = data.len();
+        let width = data[0].len();
+
+        let mut last_generation;
+        let mut current_generation = data.iter().cloned().collect_vec();
+
+        while changed {
+            last_generation = current_generation.iter().cloned().collect_vec();
+
+            changed = false;
+            for (x, y) in iproduct!(0..height, 0..width) {
+                let min_x = x.saturating_sub(1);
+                let max_x = if x + 1 == height { x } else { x + 1 };
+                let min_y = y.saturating_sub(1);
+                let max_y = if y + 1 == width { y } else { y + 1 };
+
+                let mut area =
+                    iproduct!(min_x..=max_x, min_y..=max_y).filter(|(a, b)| (a, b) != (&x, &y));
+
+                match current_generation[x][y] {
+                    Seat::Empty => {
+                        let should_change =
+                            area.all(|(a, b)| last_generation[a][b] != Seat::Occupied);
+                        changed = changed || should_change;
+                        if should_change {
+                            current_generation[x][y] = Seat::Occupied;
+                        }
+                    }
+
+                    Seat::Occupied => {
+                        let should_change = area
+                            .filter(|(a, b)| last_generation[*a][*b] == Seat::Occupied)
+                            .count()
+                            >= 4;
+                        changed = changed || should_change;
+                        if should_change {
+                            current_generation[x][y] = Seat::Empty;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        current_generation
+            .iter()
+            .map(|l| l.iter().filter(|i| i == &&Seat::Occupied).count())
+            .sum()
+    }
+
+    fn naive_part_2(data: &[Vec<Seat>]) -> usize {
+        let mut changed = true;
+
+        let height = data.len();
+        let width = data[0].len();
+
+        let mut last_generation;
+        let mut current_generation = data.iter().cloned().collect_vec();
+
+        while changed {
+            last_generation = current_generation.iter().cloned().collect_vec();
+
+            changed = false;
+            for (x, y) in iproduct!(0..height, 0..width) {
+                let mut area = iproduct!(-1i64..=1i64, -1i64..=1i64)
+                    .filter(|(a, b)| (a, b) != (&0, &0))
+                    .map(|(a, b)| {
+                        (1..)
+                            .map(|n| {
+                                last_generation
+                                    .get(if a < 0 {
+                                        x.checked_sub((n * -a) as usize)?
+                                    } else {
+                                        x + (n * a) as usize
+                                    })?
+                                    .get(if b < 0 {
+                                        y.checked_sub((n * -b) as usize)?
+                                    } else {
+                                        y + (n * b) as usize
+                                    })
+                            })
+                            .find_map(|seat| match seat {
+                                None => Some(Seat::Floor),
+                                Some(Seat::Floor) => None,
+                                Some(s) => Some(*s),
+                            })
+                            .unwrap()
+                    });
+
+                match current_generation[x][y] {
+                    Seat::Empty => {
+                        let should_change = area.all(|s| s != Seat::Occupied);
+                        changed = changed || should_change;
+                        if should_change {
+                            current_generation[x][y] = Seat::Occupied;
+                        }
+                    }
+
+                    Seat::Occupied => {
+                        let should_change = area.filter(|s| s == &Seat::Occupied).count() >= 5;
+                        changed = changed || should_change;
+                        if should_change {
+                            current_generation[x][y] = Seat::Empty;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        current_generation
+            .iter()
+            .map(|l| l.iter().filter(|i| i == &&Seat::Occupied).count())
+            .sum()
+    }
+
     #[test]
     fn test_part_1() {
         let parsed = parse_input(TEST_INPUT.as_bytes());
@@ -187,4 +421,127 @@ L.LLLLL.LL
         let parsed = parse_input(TEST_INPUT.as_bytes());
         assert_eq!(part_2(&parsed), 26);
     }
+
+    #[test]
+    fn test_seating_rules() {
+        let parsed = parse_input(b"L.L\n.L.\nL.L");
+        let four = SeatingRules {
+            neighborhood: Neighborhood::Four,
+            max_visible_occupied: 0,
+            max_range: Some(1),
+        };
+        assert_eq!(count_occupied(&parsed, &four), Some(5));
+
+        let eight = SeatingRules {
+            neighborhood: Neighborhood::Eight,
+            max_visible_occupied: 1,
+            max_range: Some(1),
+        };
+        assert_eq!(count_occupied(&parsed, &eight), Some(4));
+
+        let parsed = parse_input(b"L.L.L");
+        let short = SeatingRules {
+            neighborhood: Neighborhood::Four,
+            max_visible_occupied: 1,
+            max_range: Some(1),
+        };
+        assert_eq!(count_occupied(&parsed, &short), Some(3));
+        let long = SeatingRules {
+            max_range: Some(2),
+            ..short
+        };
+        assert_eq!(count_occupied(&parsed, &long), Some(2));
+
+        let hex = SeatingRules {
+            neighborhood: Neighborhood::Hex,
+            max_visible_occupied: 2,
+            max_range: None,
+        };
+        assert_eq!(
+            count_occupied(&parse_input(b"LLL\nLLL\nLLL"), &hex),
+            Some(5)
+        );
+
+        let parsed = parse_input(TEST_INPUT.as_bytes());
+        let limited = SeatingRules {
+            max_range: Some(1),
+            ..LINE_OF_SIGHT_RULES
+        };
+        assert_eq!(
+            count_occupied(&parsed, &limited),
+            count_occupied(
+                &parsed,
+                &SeatingRules {
+                    max_visible_occupied: 4,
+                    ..ADJACENT_RULES
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_cycles() {
+        let parsed = parse_input(b"L.L\n.L.\nL.L");
+        let rules = SeatingRules {
+            neighborhood: Neighborhood::Eight,
+            max_visible_occupied: 0,
+            max_range: Some(1),
+        };
+
+        let empty = parsed.concat();
+        let full = empty
+            .iter()
+            .map(|s| match s {
+                Seat::Empty => Seat::Occupied,
+                s => *s,
+            })
+            .collect_vec();
+        assert_eq!(
+            simulate(&parsed, &rules, MAX_GENERATIONS),
+            Outcome::Cycle {
+                start: 0,
+                period: 2,
+                states: vec![empty, full],
+            }
+        );
+        assert_eq!(count_occupied(&parsed, &rules), None);
+
+        let parsed = parse_input(TEST_INPUT.as_bytes());
+        assert!(matches!(
+            simulate(&parsed, &ADJACENT_RULES, MAX_GENERATIONS),
+            Outcome::Stable { generation: 5, .. }
+        ));
+        assert_eq!(simulate(&parsed, &ADJACENT_RULES, 3), Outcome::Unsettled);
+    }
+
+    #[test]
+    fn test_matches_naive() {
+        let parsed = parse_input(TEST_INPUT.as_bytes());
+        assert_eq!(part_1(&parsed), naive_part_1(&parsed));
+        assert_eq!(part_2(&parsed), naive_part_2(&parsed));
+    }
+
+    #[bench]
+    fn bench_part_1(b: &mut Bencher) {
+        let parsed = parse_input(&read_input());
+        b.iter(|| part_1(&parsed));
+    }
+
+    #[bench]
+    fn bench_part_1_naive(b: &mut Bencher) {
+        let parsed = parse_input(&read_input());
+        b.iter(|| naive_part_1(&parsed));
+    }
+
+    #[bench]
+    fn bench_part_2(b: &mut Bencher) {
+        let parsed = parse_input(&read_input());
+        b.iter(|| part_2(&parsed));
+    }
+
+    #[bench]
+    fn bench_part_2_naive(b: &mut Bencher) {
+        let parsed = parse_input(&read_input());
+        b.iter(|| naive_part_2(&parsed));
+    }
 }
@@ -1,9 +1,12 @@
-#![feature(destructuring_assignment)]
-
 use bstr::ByteSlice;
 use bstr_parse::*;
 use itertools::Itertools;
-use std::{fs::File, io::Read, mem};
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io::Read,
+    iter,
+};
 
 fn read_input() -> Vec<u8> {
     let mut out = Vec::new();
@@ -19,9 +22,16 @@ enum Move {
     South(i32),
     East(i32),
     West(i32),
+    Up(i32),
+    Down(i32),
     Left(i32),
     Right(i32),
     Forward(i32),
+    // Moves backwards along the heading, or the waypoint.
+    Reverse(i32),
+    // Moves the ship onto the waypoint, which stays at the same offset from
+    // the ship, like `F1`. Does nothing without a waypoint.
+    GoToWaypoint,
 }
 
 fn parse_input(input: &[u8]) -> Vec<Move> {
@@ -29,100 +39,288 @@ fn parse_input(input: &[u8]) -> Vec<Move> {
         .lines()
         .map(|l| {
             let k = l[0];
-            let v = l[1..].parse().unwrap();
+            let v = || l[1..].parse().unwrap();
 
             match k {
-                b'N' => Move::North(v),
-                b'S' => Move::South(v),
-                b'E' => Move::East(v),
-                b'W' => Move::West(v),
-                b'R' => Move::Right(v),
-                b'L' => Move::Left(v),
-                b'F' => Move::Forward(v),
+                b'N' => Move::North(v()),
+                b'S' => Move::South(v()),
+                b'E' => Move::East(v()),
+                b'W' => Move::West(v()),
+                b'U' => Move::Up(v()),
+                b'D' => Move::Down(v()),
+                b'R' => Move::Right(v()),
+                b'L' => Move::Left(v()),
+                b'F' => Move::Forward(v()),
+                b'B' => Move::Reverse(v()),
+                b'G' => Move::GoToWaypoint,
                 _ => unreachable!(),
             }
         })
         .collect_vec()
 }
 
-fn part_1(data: &[Move]) -> i32 {
-    let (ew_pos, ns_pos, _heading) =
-        data.iter()
-            .fold((0, 0, 90), |(mut ew_pos, mut ns_pos, mut heading), m| {
-                match m {
-                    Move::North(n) => ns_pos += n,
-                    Move::South(n) => ns_pos -= n,
-                    Move::East(n) => ew_pos += n,
-                    Move::West(n) => ew_pos -= n,
-                    Move::Left(n) => heading = (heading - n).rem_euclid(360),
-                    Move::Right(n) => heading = (heading + n).rem_euclid(360),
-                    Move::Forward(n) => match heading {
-                        0 => ns_pos += n,
-                        90 => ew_pos += n,
-                        180 => ns_pos -= n,
-                        270 => ew_pos -= n,
-                        _ => unreachable!(),
-                    },
-                };
-
-                (ew_pos, ns_pos, heading)
-            });
-
-    ew_pos.abs() + ns_pos.abs()
+// East/north/up coordinates. Rotations happen in the horizontal plane; by
+// multiples of 90 degrees they only swap and negate components so integer
+// positions stay exact, other angles go through floating point and distances
+// are rounded to the nearest integer at the end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Vector {
+    east: f64,
+    north: f64,
+    up: f64,
+}
+
+impl Vector {
+    const ZERO: Vector = Vector::new(0., 0., 0.);
+    const NORTH: Vector = Vector::new(0., 1., 0.);
+    const EAST: Vector = Vector::new(1., 0., 0.);
+    const UP: Vector = Vector::new(0., 0., 1.);
+
+    const fn new(east: f64, north: f64, up: f64) -> Self {
+        Vector { east, north, up }
+    }
+
+    // Clockwise, like the ship's `R` instruction.
+    fn rotate(self, degrees: i32) -> Self {
+        match degrees.rem_euclid(360) {
+            0 => self,
+            90 => Vector::new(self.north, -self.east, self.up),
+            180 => Vector::new(-self.east, -self.north, self.up),
+            270 => Vector::new(-self.north, self.east, self.up),
+            d => {
+                let (sin, cos) = (d as f64).to_radians().sin_cos();
+                Vector::new(
+                    self.east * cos + self.north * sin,
+                    self.north * cos - self.east * sin,
+                    self.up,
+                )
+            }
+        }
+    }
+
+    fn add_scaled(self, other: Vector, n: i32) -> Self {
+        Vector::new(
+            self.east + other.east * n as f64,
+            self.north + other.north * n as f64,
+            self.up + other.up * n as f64,
+        )
+    }
+
+    fn manhattan(&self) -> i64 {
+        (self.east.abs() + self.north.abs() + self.up.abs()).round() as i64
+    }
+
+    fn euclidean(&self) -> f64 {
+        (self.east.powi(2) + self.north.powi(2) + self.up.powi(2)).sqrt()
+    }
+}
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}, {})", self.east, self.north, self.up)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Ship,
+    Waypoint,
 }
 
-fn part_2(data: &[Move]) -> i32 {
-    let ((ew_pos, ns_pos), _wp) = data.iter().fold(
-        ((0, 0), (10, 1)),
-        |((mut ew_pos, mut ns_pos), (mut ew_wp, mut ns_wp)), m| {
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct State {
+    position: Vector,
+    // The unit heading in ship mode, the waypoint relative to the ship in
+    // waypoint mode.
+    heading: Vector,
+}
+
+// Every state of the ship, starting with the initial one.
+fn navigate(data: &[Move], mode: Mode) -> Vec<State> {
+    let start = match mode {
+        Mode::Ship => State {
+            position: Vector::ZERO,
+            heading: Vector::EAST,
+        },
+        Mode::Waypoint => State {
+            position: Vector::ZERO,
+            heading: Vector::new(10., 1., 0.),
+        },
+    };
+
+    // Turns accumulate as whole degrees and are only applied to the heading
+    // when it is used or, for the waypoint, translated, so that turns adding up
+    // to a multiple of 90 degrees stay exact.
+    let init = (start.position, start.heading, 0);
+    iter::once(start)
+        .chain(data.iter().scan(init, move |(position, base, angle), m| {
+            let translation = matches!(
+                m,
+                Move::North(_)
+                    | Move::South(_)
+                    | Move::East(_)
+                    | Move::West(_)
+                    | Move::Up(_)
+                    | Move::Down(_)
+            );
+            if mode == Mode::Waypoint && translation {
+                *base = base.rotate(*angle);
+                *angle = 0;
+            }
+            let heading = base.rotate(*angle);
+            let target = match mode {
+                Mode::Ship => &mut *position,
+                Mode::Waypoint => &mut *base,
+            };
+
             match m {
-                Move::North(n) => ns_wp += n,
-                Move::South(n) => ns_wp -= n,
-                Move::East(n) => ew_wp += n,
-                Move::West(n) => ew_wp -= n,
-                Move::Right(_) | Move::Left(_) => {
-                    let n = match m {
-                        Move::Right(n) => *n,
-                        Move::Left(n) => 360 - n,
-                        _ => unreachable!(),
-                    }
-                    .rem_euclid(360);
-                    match n {
-                        0 => {}
-                        90 => {
-                            mem::swap(&mut ew_wp, &mut ns_wp);
-                            ns_wp = -ns_wp;
-                        }
-                        180 => {
-                            ew_wp = -ew_wp;
-                            ns_wp = -ns_wp;
-                        }
-                        270 => {
-                            mem::swap(&mut ew_wp, &mut ns_wp);
-                            ew_wp = -ew_wp;
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-                Move::Forward(n) => {
-                    ew_pos += n * ew_wp;
-                    ns_pos += n * ns_wp;
-                }
+                Move::North(n) => *target = target.add_scaled(Vector::NORTH, *n),
+                Move::South(n) => *target = target.add_scaled(Vector::NORTH, -n),
+                Move::East(n) => *target = target.add_scaled(Vector::EAST, *n),
+                Move::West(n) => *target = target.add_scaled(Vector::EAST, -n),
+                Move::Up(n) => *target = target.add_scaled(Vector::UP, *n),
+                Move::Down(n) => *target = target.add_scaled(Vector::UP, -n),
+                Move::Left(n) => *angle = (*angle - n).rem_euclid(360),
+                Move::Right(n) => *angle = (*angle + n).rem_euclid(360),
+                Move::Forward(n) => *position = position.add_scaled(heading, *n),
+                Move::Reverse(n) => *position = position.add_scaled(heading, -n),
+                Move::GoToWaypoint if mode == Mode::Ship => {}
+                Move::GoToWaypoint => *position = position.add_scaled(heading, 1),
             };
+            Some(State {
+                position: *position,
+                heading: base.rotate(*angle),
+            })
+        }))
+        .collect()
+}
 
-            ((ew_pos, ns_pos), (ew_wp, ns_wp))
-        },
-    );
+fn part_1(data: &[Move]) -> i64 {
+    navigate(data, Mode::Ship)
+        .last()
+        .unwrap()
+        .position
+        .manhattan()
+}
+
+fn part_2(data: &[Move]) -> i64 {
+    navigate(data, Mode::Waypoint)
+        .last()
+        .unwrap()
+        .position
+        .manhattan()
+}
+
+const SVG_SIZE: f64 = 800.;
+const SVG_MARGIN: f64 = 20.;
 
-    ew_pos.abs() + ns_pos.abs()
+// Only the horizontal plane is drawn. `waypoints` holds the absolute waypoint
+// positions, if any.
+fn to_svg(ship: &[Vector], waypoints: &[Vector]) -> String {
+    let all = ship.iter().chain(waypoints).collect_vec();
+
+    let (min_e, max_e) = all
+        .iter()
+        .map(|v| v.east)
+        .fold((0f64, 0f64), |(min, max), e| (min.min(e), max.max(e)));
+    let (min_n, max_n) = all
+        .iter()
+        .map(|v| v.north)
+        .fold((0f64, 0f64), |(min, max), n| (min.min(n), max.max(n)));
+    let scale = (SVG_SIZE - 2. * SVG_MARGIN) / (max_e - min_e).max(max_n - min_n).max(1.);
+
+    let project = |v: &Vector| {
+        (
+            SVG_MARGIN + (v.east - min_e) * scale,
+            SVG_MARGIN + (max_n - v.north) * scale,
+        )
+    };
+    let points = |vs: &mut dyn Iterator<Item = &Vector>| {
+        vs.map(|v| {
+            let (x, y) = project(v);
+            format!("{:.2},{:.2}", x, y)
+        })
+        .join(" ")
+    };
+
+    let (start_x, start_y) = project(&ship[0]);
+    let (end_x, end_y) = project(&ship[ship.len() - 1]);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" viewBox=\"0 0 {0} {0}\">\n",
+        SVG_SIZE
+    );
+    if !waypoints.is_empty() {
+        svg += &format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"orange\" stroke-dasharray=\"4\"/>\n",
+            points(&mut waypoints.iter())
+        );
+    }
+    svg += &format!(
+        "  <polyline points=\"{}\" fill=\"none\" stroke=\"blue\"/>\n",
+        points(&mut ship.iter())
+    );
+    svg += &format!(
+        "  <circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"5\" fill=\"green\"/>\n",
+        start_x, start_y
+    );
+    svg += &format!(
+        "  <circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"5\" fill=\"red\"/>\n",
+        end_x, end_y
+    );
+    svg += &format!(
+        "  <text x=\"{}\" y=\"{}\">Manhattan distance: {}</text>\n",
+        SVG_MARGIN,
+        SVG_SIZE - SVG_MARGIN / 4.,
+        ship[ship.len() - 1].manhattan()
+    );
+    svg + "</svg>\n"
 }
 
 fn main() {
     let input = read_input();
     let parsed = parse_input(&input);
+    let args = env::args().skip(1).collect_vec();
 
-    println!("Part 1: {}", part_1(&parsed));
-    println!("Part 2: {}", part_2(&parsed));
+    let mode = |name| match name {
+        "ship" => Mode::Ship,
+        "waypoint" => Mode::Waypoint,
+        _ => panic!("unknown mode {}, expected ship or waypoint", name),
+    };
+
+    match args.iter().map(String::as_str).collect_vec()[..] {
+        ["svg", name, path] => {
+            let (ship, waypoints): (Vec<_>, Vec<_>) = navigate(&parsed, mode(name))
+                .into_iter()
+                .map(|s| (s.position, s.position.add_scaled(s.heading, 1)))
+                .unzip();
+            let waypoints = if mode(name) == Mode::Waypoint {
+                &waypoints[..]
+            } else {
+                &[]
+            };
+            fs::write(path, to_svg(&ship, waypoints)).unwrap();
+        }
+        ["history", name] => {
+            let history = navigate(&parsed, mode(name));
+            let moves = iter::once(None).chain(parsed.iter().map(Some));
+            for (state, m) in history.iter().zip(moves) {
+                println!(
+                    "{:<16} position={} heading={}",
+                    m.map_or("start".to_owned(), |m| format!("{:?}", m)),
+                    state.position,
+                    state.heading
+                );
+            }
+            let last = history.last().unwrap().position;
+            println!("Manhattan distance: {}", last.manhattan());
+            println!("Euclidean distance: {:.3}", last.euclidean());
+        }
+        _ => {
+            println!("Part 1: {}", part_1(&parsed));
+            println!("Part 2: {}", part_2(&parsed));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -147,4 +345,89 @@ F11
         let parsed = parse_input(TEST_INPUT.as_bytes());
         assert_eq!(part_2(&parsed), 286);
     }
+
+    #[test]
+    fn test_to_svg() {
+        let parsed = parse_input(TEST_INPUT.as_bytes());
+        let path = navigate(&parsed, Mode::Ship)
+            .iter()
+            .map(|s| s.position)
+            .collect_vec();
+        assert_eq!(path.last(), Some(&Vector::new(17., -8., 0.)));
+
+        let svg = to_svg(&path, &[]);
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert!(svg.contains(
+            "points=\"20.00,154.12 467.06,154.12 467.06,20.00 780.00,20.00 780.00,20.00 780.00,511.76\""
+        ));
+        assert!(svg.contains("<circle cx=\"20.00\" cy=\"154.12\" r=\"5\" fill=\"green\"/>"));
+        assert!(svg.contains("<circle cx=\"780.00\" cy=\"511.76\" r=\"5\" fill=\"red\"/>"));
+        assert!(svg.contains("Manhattan distance: 25"));
+        assert!(!svg.contains("orange"));
+
+        let (ship, waypoints): (Vec<_>, Vec<_>) = navigate(&parsed, Mode::Waypoint)
+            .into_iter()
+            .map(|s| (s.position, s.position.add_scaled(s.heading, 1)))
+            .unzip();
+        let svg = to_svg(&ship, &waypoints);
+        assert!(svg.contains("orange") && svg.contains("Manhattan distance: 286"));
+    }
+
+    #[test]
+    fn test_extended_instructions() {
+        let parsed = parse_input(b"U5\nF10\nB3\nD2\nG\nR90\nF1");
+        let history = navigate(&parsed, Mode::Ship);
+        assert_eq!(history.len(), parsed.len() + 1);
+        assert_eq!(history[0].position, Vector::ZERO);
+        assert_eq!(history[4].position, Vector::new(7., 0., 3.));
+        assert_eq!(history[5], history[4]);
+        assert_eq!(history[6].heading, Vector::new(0., -1., 0.));
+        assert_eq!(history[7].position, Vector::new(7., -1., 3.));
+        assert_eq!(history[7].position.manhattan(), 11);
+        assert!((history[7].position.euclidean() - 59f64.sqrt()).abs() < 1e-9);
+
+        let history = navigate(&parsed, Mode::Waypoint);
+        assert_eq!(history[1].heading, Vector::new(10., 1., 5.));
+        assert_eq!(history[3].position, Vector::new(70., 7., 35.));
+        assert_eq!(history[5].position, Vector::new(80., 8., 38.));
+        assert_eq!(history[5].heading, Vector::new(10., 1., 3.));
+        assert_eq!(history[6].heading, Vector::new(1., -10., 3.));
+        assert_eq!(history[7].position, Vector::new(81., -2., 41.));
+    }
+
+    #[test]
+    fn test_arbitrary_angles() {
+        let parsed = parse_input(b"R45\nF10");
+        assert_eq!(part_1(&parsed), 14);
+
+        let parsed = parse_input(b"L45\nF1");
+        assert_eq!(part_2(&parsed), 14);
+
+        let v = Vector::new(3., -7., 2.);
+        assert_eq!(v.rotate(90), Vector::new(-7., -3., 2.));
+        assert_eq!(v.rotate(-270), v.rotate(90));
+        assert_eq!(v.rotate(720), v);
+
+        let rotated = v.rotate(30).rotate(60);
+        assert!((rotated.east - -7.).abs() < 1e-9 && (rotated.north - -3.).abs() < 1e-9);
+
+        // Composed turns adding up to multiples of 90 degrees land exactly.
+        let parsed = parse_input(b"R30\nR30\nR30\nF3");
+        let last = *navigate(&parsed, Mode::Ship).last().unwrap();
+        assert_eq!(last.position, Vector::new(0., -3., 0.));
+        assert_eq!(last.heading, Vector::new(0., -1., 0.));
+
+        let parsed = parse_input(b"L30\nL60\nF1000000000\nR45\nR45\nF1000000000\nE1");
+        let last = *navigate(&parsed, Mode::Ship).last().unwrap();
+        assert_eq!(
+            last.position,
+            Vector::new(1_000_000_001., 1_000_000_000., 0.)
+        );
+
+        let parsed = parse_input(b"R30\nR30\nR30\nF1\nL45\nN0\nR45\nF1");
+        let history = navigate(&parsed, Mode::Waypoint);
+        assert_eq!(history[4].position, Vector::new(1., -10., 0.));
+        let last = history.last().unwrap().position;
+        assert!((last.east - 2.).abs() < 1e-9 && (last.north - -20.).abs() < 1e-9);
+    }
 }
@@ -1,9 +1,13 @@
-#![feature(destructuring_assignment)]
-
 use bstr::ByteSlice;
 use bstr_parse::*;
 use itertools::Itertools;
-use std::{fs::File, io::Read};
+use number_theory::{chinese_remainder, CrtError};
+use std::{
+    env,
+    fs::{self, File},
+    io::Read,
+    process,
+};
 
 enum Departure {
     Bus(i64),
@@ -45,57 +49,270 @@ fn part_1(timestamp: i64, departures: &[Departure]) -> i64 {
         .unwrap()
 }
 
-// Chinese remainder implementation from https://rosettacode.org/wiki/Chinese_remainder_theorem#Rust
-fn egcd(a: i64, b: i64) -> (i64, i64, i64) {
-    if a == 0 {
-        (b, 0, 1)
-    } else {
-        let (g, x, y) = egcd(b % a, a);
-        (g, y - (b / a) * x, x)
+mod number_theory {
+    use std::fmt;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum CrtError {
+        InvalidModulus(i128),
+        // The congruence at `index` contradicts the ones before it.
+        Inconsistent {
+            index: usize,
+            residue: i128,
+            modulus: i128,
+        },
+        Overflow,
     }
-}
 
-fn mod_inv(x: i64, n: i64) -> Option<i64> {
-    let (g, x, _) = egcd(x, n);
-    if g == 1 {
-        Some((x % n + n) % n)
-    } else {
-        None
+    impl fmt::Display for CrtError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                CrtError::InvalidModulus(m) => write!(f, "invalid modulus {}", m),
+                CrtError::Inconsistent {
+                    index,
+                    residue,
+                    modulus,
+                } => write!(
+                    f,
+                    "congruence #{} (x = {} mod {}) contradicts the previous ones",
+                    index, residue, modulus
+                ),
+                CrtError::Overflow => write!(f, "combined modulus overflows i128"),
+            }
+        }
     }
-}
 
-fn chinese_remainder(residues: &[i64], modulii: &[i64]) -> Option<i64> {
-    let prod = modulii.iter().product::<i64>();
+    // Returns (g, x, y) with a * x + b * y = g = gcd(a, b).
+    pub fn egcd(a: i128, b: i128) -> (i128, i128, i128) {
+        let (mut old_r, mut r) = (a, b);
+        let (mut old_x, mut x) = (1, 0);
+        let (mut old_y, mut y) = (0, 1);
 
-    let mut sum = 0;
+        while r != 0 {
+            let q = old_r / r;
+            (old_r, r) = (r, old_r - q * r);
+            (old_x, x) = (x, old_x - q * x);
+            (old_y, y) = (y, old_y - q * y);
+        }
+
+        (old_r, old_x, old_y)
+    }
 
-    for (&residue, &modulus) in residues.iter().zip(modulii) {
-        let p = prod / modulus;
-        sum += residue * mod_inv(p, modulus)? * p
+    pub fn mod_inv(x: i128, n: i128) -> Option<i128> {
+        let (g, x, _) = egcd(x.rem_euclid(n), n);
+        (g == 1).then_some(x.rem_euclid(n))
     }
 
-    Some(sum % prod)
+    // Merges x = r1 mod m1 and x = r2 mod m2 into a single congruence modulo
+    // lcm(m1, m2), or `None` if they have no common solution.
+    fn merge(
+        (r1, m1): (i128, i128),
+        (r2, m2): (i128, i128),
+    ) -> Result<Option<(i128, i128)>, CrtError> {
+        let (g, _, _) = egcd(m1, m2);
+        if (r2 - r1) % g != 0 {
+            return Ok(None);
+        }
+
+        let (m1g, m2g) = (m1 / g, m2 / g);
+        let lcm = m1g.checked_mul(m2).ok_or(CrtError::Overflow)?;
+        let k = ((r2 - r1) / g)
+            .rem_euclid(m2g)
+            .checked_mul(mod_inv(m1g, m2g).unwrap_or(0))
+            .ok_or(CrtError::Overflow)?
+            .rem_euclid(m2g);
+        let r = m1
+            .checked_mul(k)
+            .and_then(|n| n.checked_add(r1))
+            .ok_or(CrtError::Overflow)?;
+
+        Ok(Some((r.rem_euclid(lcm), lcm)))
+    }
+
+    // Solves x = residue mod modulus for every pair, without requiring the
+    // moduli to be coprime. Returns the smallest non-negative solution along
+    // with the lcm of the moduli.
+    pub fn chinese_remainder(congruences: &[(i128, i128)]) -> Result<(i128, i128), CrtError> {
+        congruences
+            .iter()
+            .enumerate()
+            .try_fold((0, 1), |acc, (index, &(residue, modulus))| {
+                if modulus <= 0 {
+                    return Err(CrtError::InvalidModulus(modulus));
+                }
+                merge(acc, (residue.rem_euclid(modulus), modulus))?.ok_or(CrtError::Inconsistent {
+                    index,
+                    residue,
+                    modulus,
+                })
+            })
+    }
 }
-// End chinese remainder
 
-fn part_2(data: &[Departure]) -> i64 {
-    let it = data.iter().enumerate().filter_map(|(n, d)| match d {
-        Departure::Bus(x) => Some((n as i64, *x)),
+// Bus ids along with their position in the list.
+fn buses(departures: &[Departure]) -> impl Iterator<Item = (usize, i64)> + '_ {
+    departures.iter().enumerate().filter_map(|(n, d)| match d {
+        Departure::Bus(id) => Some((n, *id)),
         _ => None,
-    });
+    })
+}
 
-    let residues = it.clone().map(|(n, id)| id - n).collect_vec();
-    let modulii = it.map(|(_n, id)| id).collect_vec();
+// The first `n` departures of every bus at or after `timestamp`.
+fn next_departures(departures: &[Departure], timestamp: i64, n: usize) -> Vec<(i64, Vec<i64>)> {
+    buses(departures)
+        .map(|(_, id)| {
+            let first = timestamp + (-timestamp).rem_euclid(id);
+            (id, (0..n as i64).map(|i| first + i * id).collect_vec())
+        })
+        .collect_vec()
+}
 
-    chinese_remainder(&residues[..], &modulii[..]).unwrap()
+// Earliest timestamp t such that each `bus` departs at t + `offset`.
+fn earliest_alignment(constraints: &[(i64, i64)]) -> Result<i128, CrtError> {
+    let congruences = constraints
+        .iter()
+        .map(|&(bus, offset)| (-(offset as i128), bus as i128))
+        .collect_vec();
+
+    chinese_remainder(&congruences).map(|(t, _)| t)
+}
+
+// Constraints given explicitly as `bus@offset` pairs, separated by commas or
+// whitespace.
+fn parse_constraints(input: &[u8]) -> Result<Vec<(i64, i64)>, String> {
+    input
+        .fields_with(|c| c == ',' || c.is_whitespace())
+        .map(|token| {
+            token
+                .split_str("@")
+                .map(|n| n.parse().ok())
+                .collect_tuple()
+                .and_then(|(bus, offset)| bus.zip(offset))
+                .filter(|(bus, _)| *bus > 0)
+                .ok_or_else(|| format!("invalid constraint {:?}", token.as_bstr()))
+        })
+        .collect()
+}
+
+const BRUTE_FORCE_LIMIT: i64 = 10_000_000;
+
+fn brute_force_alignment(constraints: &[(i64, i64)], limit: i64) -> Option<i64> {
+    (0..limit).find(|t| {
+        constraints
+            .iter()
+            .all(|(bus, offset)| (t + offset).rem_euclid(*bus) == 0)
+    })
 }
 
+fn timetable(departures: &[Departure], from: i64, to: i64) -> String {
+    let ids = buses(departures).map(|(_, id)| id).collect_vec();
+
+    let mut out = format!("{:<12}", "time");
+    for id in &ids {
+        out += &format!("{:^8}", format!("bus {}", id));
+    }
+    out += "\n";
+
+    for t in from..=to {
+        out += &format!("{:<12}", t);
+        for id in &ids {
+            out += &format!("{:^8}", if t.rem_euclid(*id) == 0 { "D" } else { "." });
+        }
+        out += "\n";
+    }
+    out
+}
+
+fn part_2(data: &[Departure]) -> Result<i128, CrtError> {
+    let constraints = buses(data).map(|(n, id)| (id, n as i64)).collect_vec();
+
+    earliest_alignment(&constraints)
+}
+
+const ALIGN_USAGE: &str = "usage: day-13 align <bus> <offset> [<bus> <offset>...]";
+
 fn main() {
     let input = read_input();
     let (ts, deps) = parse_input(&input);
+    let args = env::args().skip(1).collect_vec();
+
+    match args.iter().map(String::as_str).collect_vec()[..] {
+        ["next", n] => {
+            for (id, times) in next_departures(&deps, ts, n.parse().unwrap()) {
+                println!("bus {:<5} {}", id, times.iter().join(" "));
+            }
+        }
+        ["align", ref pairs @ ..] if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let constraints = match pairs
+                .iter()
+                .map(|n| n.parse())
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(numbers) => numbers.into_iter().tuples().collect_vec(),
+                Err(_) => {
+                    eprintln!("{}", ALIGN_USAGE);
+                    process::exit(1);
+                }
+            };
+            let unknown = constraints
+                .iter()
+                .filter(|(bus, _)| buses(&deps).all(|(_, id)| id != *bus))
+                .map(|(bus, _)| bus)
+                .collect_vec();
+            if !unknown.is_empty() {
+                eprintln!(
+                    "no such buses in the schedule: {}",
+                    unknown.iter().join(", ")
+                );
+                process::exit(1);
+            }
+
+            match earliest_alignment(&constraints) {
+                Ok(t) => println!("{}", t),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        ["align", ..] => {
+            eprintln!("{}", ALIGN_USAGE);
+            process::exit(1);
+        }
+        ["constraints", path] => {
+            let constraints = parse_constraints(&fs::read(path).unwrap()).unwrap();
+            let solution = earliest_alignment(&constraints);
+            match &solution {
+                Ok(t) => println!("{}", t),
+                Err(e) => println!("{}", e),
+            }
 
-    println!("Part 1: {}", part_1(ts, &deps));
-    println!("Part 2: {}", part_2(&deps));
+            let lcm = chinese_remainder(
+                &constraints
+                    .iter()
+                    .map(|&(bus, _)| (0, bus as i128))
+                    .collect_vec(),
+            );
+            if matches!(lcm, Ok((_, m)) if m <= BRUTE_FORCE_LIMIT as i128) {
+                let brute = brute_force_alignment(&constraints, BRUTE_FORCE_LIMIT);
+                assert_eq!(solution.ok(), brute.map(i128::from));
+                println!("brute force agrees");
+            }
+        }
+        ["timetable", from, to] => {
+            print!(
+                "{}",
+                timetable(&deps, from.parse().unwrap(), to.parse().unwrap())
+            );
+        }
+        _ => {
+            println!("Part 1: {}", part_1(ts, &deps));
+            match part_2(&deps) {
+                Ok(t) => println!("Part 2: {}", t),
+                Err(e) => println!("Part 2: {}", e),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -124,7 +341,110 @@ mod tests {
             let inp = format!("0\n{}\n", inp);
 
             let (_, deps) = parse_input(inp.as_bytes());
-            assert_eq!(part_2(&deps), *expected);
+            assert_eq!(part_2(&deps), Ok(*expected as i128));
+        }
+    }
+
+    #[test]
+    fn test_queries() {
+        let (ts, deps) = parse_input(TEST_INPUT_P1.as_bytes());
+        let next = next_departures(&deps, ts, 2);
+        assert_eq!(next[0], (7, vec![945, 952]));
+        assert_eq!(next[2], (59, vec![944, 1003]));
+        assert_eq!(next_departures(&deps, 938, 1)[0], (7, vec![938]));
+
+        assert_eq!(earliest_alignment(&[(7, 0), (13, 1)]), Ok(77));
+        assert_eq!(
+            earliest_alignment(&[(59, 4), (31, 6), (19, 7)]),
+            Ok(1068781 % (59 * 31 * 19))
+        );
+        assert_eq!(
+            earliest_alignment(&[(7, 0), (14, 1)]),
+            Err(CrtError::Inconsistent {
+                index: 1,
+                residue: -1,
+                modulus: 14
+            })
+        );
+
+        let table = timetable(&deps, 1068773, 1068785);
+        let lines = table.lines().collect_vec();
+        assert_eq!(lines.len(), 14);
+        assert_eq!(
+            lines[0].split_whitespace().collect_vec()[..3],
+            ["time", "bus", "7"]
+        );
+        assert_eq!(
+            lines[9].split_whitespace().collect_vec(),
+            ["1068781", "D", ".", ".", ".", "."]
+        );
+        assert_eq!(
+            lines[13].split_whitespace().collect_vec(),
+            ["1068785", ".", ".", "D", ".", "."]
+        );
+    }
+
+    #[test]
+    fn test_constraints() {
+        let constraints = parse_constraints(b"7@0, 13@1,59@4\n31@6 19@7\n").unwrap();
+        assert_eq!(constraints.len(), 5);
+        assert_eq!(earliest_alignment(&constraints), Ok(1068781));
+
+        assert_eq!(
+            parse_constraints(b"7@0,13"),
+            Err("invalid constraint \"13\"".to_owned())
+        );
+        assert!(parse_constraints(b"0@1").is_err() && parse_constraints(b"x@1").is_err());
+
+        let cases: &[&[(i64, i64)]] = &[
+            &[(3, 10), (5, -2), (7, 100)],
+            &[(4, -1), (6, 3)],
+            &[(4, 1), (6, 4)],
+            &[(17, 0), (13, 15), (19, -3)],
+            &[(9, -20)],
+        ];
+        for constraints in cases {
+            let input = constraints
+                .iter()
+                .map(|(bus, offset)| format!("{}@{}", bus, offset))
+                .join(",");
+            let parsed = parse_constraints(input.as_bytes()).unwrap();
+            assert_eq!(
+                earliest_alignment(&parsed).ok(),
+                brute_force_alignment(&parsed, 10_000).map(i128::from)
+            );
         }
     }
+
+    #[test]
+    fn test_chinese_remainder() {
+        assert_eq!(chinese_remainder(&[]), Ok((0, 1)));
+        assert_eq!(chinese_remainder(&[(2, 3), (3, 5), (2, 7)]), Ok((23, 105)));
+        assert_eq!(chinese_remainder(&[(3, 4), (1, 6)]), Ok((7, 12)));
+        assert_eq!(chinese_remainder(&[(-1, 4), (-5, 6), (7, 8)]), Ok((7, 24)));
+        assert_eq!(
+            chinese_remainder(&[(1, 4), (0, 6), (3, 5)]),
+            Err(CrtError::Inconsistent {
+                index: 1,
+                residue: 0,
+                modulus: 6
+            })
+        );
+        assert_eq!(
+            chinese_remainder(&[(1, 0)]),
+            Err(CrtError::InvalidModulus(0))
+        );
+
+        let big = [
+            (1, 1_000_000_007),
+            (2, 998_244_353),
+            (3, 1_000_000_009),
+            (4, 2_147_483_647),
+        ];
+        let (x, m) = chinese_remainder(&big).unwrap();
+        assert!(big.iter().all(|&(r, n)| x % n == r) && x < m);
+
+        let overflow = [(0, i128::MAX / 2), (1, i128::MAX / 2 - 2)];
+        assert_eq!(chinese_remainder(&overflow), Err(CrtError::Overflow));
+    }
 }
@@ -1,9 +1,14 @@
-#![feature(destructuring_assignment)]
-
 use bstr::ByteSlice;
 use bstr_parse::*;
 use itertools::Itertools;
-use std::{collections::HashMap, fs::File, io::Read};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    env, fmt,
+    fs::File,
+    io::Read,
+    iter, process,
+};
 
 enum Bit {
     Zero,
@@ -24,105 +29,357 @@ fn read_input() -> Vec<u8> {
     out
 }
 
-fn parse_input(input: &[u8]) -> Vec<Instruction> {
+const WORD_WIDTH: u32 = 36;
+// Keeps shifts within u64 and, for the floating decoder, value times address
+// count within i128.
+const MAX_WORD_WIDTH: u32 = 63;
+
+#[derive(Debug, PartialEq, Eq)]
+enum ParseError {
+    Width(u32),
+    Syntax(usize, String),
+    MaskLength(usize, usize),
+    MaskBit(usize, char),
+    AddressOverflow(usize, u64),
+    ValueOverflow(usize, u64),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Width(width) => write!(
+                f,
+                "word width {} is not between 1 and {}",
+                width, MAX_WORD_WIDTH
+            ),
+            ParseError::Syntax(line, l) => write!(f, "line {}: invalid instruction {:?}", line, l),
+            ParseError::MaskLength(line, len) => {
+                write!(f, "line {}: mask has {} bits", line, len)
+            }
+            ParseError::MaskBit(line, c) => write!(f, "line {}: invalid mask bit {:?}", line, c),
+            ParseError::AddressOverflow(line, a) => {
+                write!(f, "line {}: address {} does not fit in a word", line, a)
+            }
+            ParseError::ValueOverflow(line, v) => {
+                write!(f, "line {}: value {} does not fit in a word", line, v)
+            }
+        }
+    }
+}
+
+// Masks must have exactly `width` bits, addresses and values must fit in them.
+fn parse_input(input: &[u8], width: u32) -> Result<Vec<Instruction>, ParseError> {
+    if !(1..=MAX_WORD_WIDTH).contains(&width) {
+        return Err(ParseError::Width(width));
+    }
+    let fits = |n: u64| n >> width == 0;
+
     input
         .lines()
-        .map(|l| {
-            let (key, value) = l.split_str(" = ").collect_tuple().unwrap();
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| {
+            let line = n + 1;
+            let syntax = || ParseError::Syntax(line, l.to_str_lossy().into_owned());
+            let (key, value) = l
+                .trim()
+                .split_str(" = ")
+                .collect_tuple()
+                .ok_or_else(syntax)?;
+
             match key {
-                b"mask" => Instruction::Mask(
+                b"mask" => {
+                    if value.len() != width as usize {
+                        return Err(ParseError::MaskLength(line, value.len()));
+                    }
                     value
                         .iter()
                         .rev()
                         .map(|c| match c {
-                            b'0' => Bit::Zero,
-                            b'1' => Bit::One,
-                            b'X' => Bit::Floating,
-                            _ => unreachable!(),
+                            b'0' => Ok(Bit::Zero),
+                            b'1' => Ok(Bit::One),
+                            b'X' => Ok(Bit::Floating),
+                            _ => Err(ParseError::MaskBit(line, *c as char)),
                         })
-                        .collect_vec(),
-                ),
-                _ => Instruction::Set((
-                    key[4..key.len() - 1].parse().unwrap(),
-                    value.parse().unwrap(),
-                )),
+                        .collect::<Result<_, _>>()
+                        .map(Instruction::Mask)
+                }
+                _ => {
+                    let addr = key
+                        .strip_prefix(b"mem[")
+                        .and_then(|k| k.strip_suffix(b"]"))
+                        .and_then(|k| k.parse().ok())
+                        .ok_or_else(syntax)?;
+                    let value = value.parse().map_err(|_| syntax())?;
+                    if !fits(addr) {
+                        return Err(ParseError::AddressOverflow(line, addr));
+                    }
+                    if !fits(value) {
+                        return Err(ParseError::ValueOverflow(line, value));
+                    }
+                    Ok(Instruction::Set((addr, value)))
+                }
             }
         })
-        .collect_vec()
+        .collect()
+}
+
+fn part_1(data: &[Instruction]) -> u128 {
+    let mut emulator = Emulator::new(Decoder::V1);
+    data.iter().for_each(|inst| {
+        emulator.step(inst);
+    });
+    emulator.memory().values().map(|v| *v as u128).sum()
+}
+
+// The addresses whose bits outside `floating` equal `fixed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Pattern {
+    fixed: u64,
+    floating: u64,
+}
+
+impl Pattern {
+    fn new(addr: u64, mask: &[Bit]) -> Self {
+        let (ones, floating) =
+            mask.iter()
+                .enumerate()
+                .fold((0, 0), |(ones, floating), (i, bit)| match bit {
+                    Bit::One => (ones | 1 << i, floating),
+                    Bit::Zero => (ones, floating),
+                    Bit::Floating => (ones, floating | 1 << i),
+                });
+        Pattern {
+            fixed: (addr | ones) & !floating,
+            floating,
+        }
+    }
+
+    fn intersect(&self, other: &Pattern) -> Option<Pattern> {
+        ((self.fixed ^ other.fixed) & !self.floating & !other.floating == 0).then_some(Pattern {
+            fixed: self.fixed | other.fixed,
+            floating: self.floating & other.floating,
+        })
+    }
+
+    fn len(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+
+    fn addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        // Walks the submasks of `floating` in decreasing order.
+        iter::successors(Some(self.floating), move |sub| {
+            (*sub != 0).then(|| (sub - 1) & self.floating)
+        })
+        .map(move |sub| self.fixed | sub)
+    }
 }
 
-fn part_1(data: &[Instruction]) -> u64 {
+// Memory as signed weights on address patterns: the value at an address is the
+// sum of the weights of the patterns containing it. A write cancels every
+// previous weight on its intersection with the written pattern, so the number
+// of patterns only depends on how the writes overlap, not on how many
+// addresses they cover.
+#[derive(Default)]
+struct FloatingMemory {
+    weights: HashMap<Pattern, i128>,
+}
+
+impl FloatingMemory {
+    fn write(&mut self, pattern: Pattern, value: u64) {
+        let mut deltas = HashMap::new();
+        for (p, w) in &self.weights {
+            if let Some(overlap) = p.intersect(&pattern) {
+                *deltas.entry(overlap).or_insert(0) -= w;
+            }
+        }
+        *deltas.entry(pattern).or_insert(0) += value as i128;
+
+        for (p, d) in deltas {
+            let w = self.weights.entry(p).or_insert(0);
+            *w += d;
+            if *w == 0 {
+                self.weights.remove(&p);
+            }
+        }
+    }
+
+    // `None` if the sum, or some term of it, does not fit in an i128.
+    fn sum(&self) -> Option<u128> {
+        self.weights
+            .iter()
+            .try_fold(0i128, |sum, (p, w)| {
+                w.checked_mul(p.len().try_into().ok()?)
+                    .and_then(|term| sum.checked_add(term))
+            })
+            .map(|sum| sum as u128)
+    }
+}
+
+fn part_2(data: &[Instruction]) -> Option<u128> {
     data.iter()
         .fold(
-            (HashMap::new(), [].as_ref()),
+            (FloatingMemory::default(), [].as_ref()),
             |(mut memory, mut mask), inst| {
                 match inst {
                     Instruction::Mask(m) => mask = m,
                     Instruction::Set((addr, value)) => {
-                        memory.insert(
-                            *addr,
-                            mask.iter()
-                                .enumerate()
-                                .fold(*value, |acc, (i, bit)| match bit {
-                                    Bit::One => acc | (1 << i),
-                                    Bit::Zero => acc & !(1 << i),
-                                    Bit::Floating => acc,
-                                }),
-                        );
+                        memory.write(Pattern::new(*addr, mask), *value)
                     }
                 };
                 (memory, mask)
             },
         )
         .0
-        .iter()
-        .map(|(_addr, val)| val)
         .sum()
 }
-fn part_2(data: &[Instruction]) -> u64 {
-    data.iter()
-        .fold(
-            (HashMap::new(), [].as_ref()),
-            |(mut memory, mut mask), inst| {
-                match inst {
-                    Instruction::Mask(m) => mask = m,
-                    Instruction::Set((addr, value)) => mask
-                        .iter()
-                        .enumerate()
-                        .fold(vec![*addr], |mut addrs, (i, bit)| {
-                            match bit {
-                                Bit::One => addrs.iter_mut().for_each(|a| *a |= 1 << i),
-                                Bit::Zero => {}
-                                Bit::Floating => {
-                                    addrs.extend(addrs.clone().iter().map(|a| match (a >> i) & 1 {
-                                        0 => a | (1 << i),
-                                        1 => a & !(1 << i),
-                                        _ => unreachable!(),
-                                    }))
-                                }
-                            };
-                            addrs
-                        })
-                        .iter()
-                        .for_each(|addr| {
-                            memory.insert(*addr as u64, *value as u64);
-                        }),
-                };
-                (memory, mask)
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Decoder {
+    // The mask applies to values.
+    V1,
+    // The mask applies to addresses, floating bits taking both values.
+    V2,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Write {
+    address: u64,
+    value: u64,
+}
+
+fn mask_to_string(mask: &[Bit]) -> String {
+    mask.iter()
+        .rev()
+        .map(|bit| match bit {
+            Bit::Zero => '0',
+            Bit::One => '1',
+            Bit::Floating => 'X',
+        })
+        .collect()
+}
+
+fn apply_mask(value: u64, mask: &[Bit]) -> u64 {
+    mask.iter()
+        .enumerate()
+        .fold(value, |acc, (i, bit)| match bit {
+            Bit::One => acc | (1 << i),
+            Bit::Zero => acc & !(1 << i),
+            Bit::Floating => acc,
+        })
+}
+
+// Concrete memory, expanding floating addresses with the second decoder.
+struct Emulator<'a> {
+    decoder: Decoder,
+    mask: &'a [Bit],
+    memory: BTreeMap<u64, u64>,
+}
+
+impl<'a> Emulator<'a> {
+    fn new(decoder: Decoder) -> Self {
+        Emulator {
+            decoder,
+            mask: &[],
+            memory: BTreeMap::new(),
+        }
+    }
+
+    fn mask(&self) -> &[Bit] {
+        self.mask
+    }
+
+    fn memory(&self) -> &BTreeMap<u64, u64> {
+        &self.memory
+    }
+
+    // Returns the writes performed by the instruction.
+    fn step(&mut self, inst: &'a Instruction) -> Vec<Write> {
+        let writes = match inst {
+            Instruction::Mask(m) => {
+                self.mask = m;
+                vec![]
+            }
+            Instruction::Set((address, value)) => match self.decoder {
+                Decoder::V1 => vec![Write {
+                    address: *address,
+                    value: apply_mask(*value, self.mask),
+                }],
+                Decoder::V2 => Pattern::new(*address, self.mask)
+                    .addresses()
+                    .map(|address| Write {
+                        address,
+                        value: *value,
+                    })
+                    .collect_vec(),
             },
-        )
-        .0
-        .iter()
-        .map(|(_addr, val)| val)
-        .sum()
+        };
+
+        for w in &writes {
+            self.memory.insert(w.address, w.value);
+        }
+        writes
+    }
+
+    fn dump(&self) -> String {
+        self.memory
+            .iter()
+            .map(|(address, value)| format!("{:09x}: {:09x}\n", address, value))
+            .collect()
+    }
 }
 
 fn main() {
+    let args = env::args().skip(1).collect_vec();
+    let (width, args) = match &args[..] {
+        [flag, width, rest @ ..] if flag == "--width" => (width.parse().unwrap(), rest),
+        rest => (WORD_WIDTH, rest),
+    };
+
     let input = read_input();
-    let parsed = parse_input(&input);
+    let parsed = match parse_input(&input, width) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
-    println!("Part 1: {}", part_1(&parsed));
-    println!("Part 2: {}", part_2(&parsed));
+    let decoder = |name| match name {
+        "v1" => Decoder::V1,
+        "v2" => Decoder::V2,
+        _ => panic!("unknown decoder {}, expected v1 or v2", name),
+    };
+
+    match args.iter().map(String::as_str).collect_vec()[..] {
+        ["trace", name] => {
+            let mut emulator = Emulator::new(decoder(name));
+            for inst in &parsed {
+                for w in emulator.step(inst) {
+                    println!(
+                        "mask={} mem[{}] = {}",
+                        mask_to_string(emulator.mask()),
+                        w.address,
+                        w.value
+                    );
+                }
+            }
+        }
+        ["dump", name] => {
+            let mut emulator = Emulator::new(decoder(name));
+            parsed.iter().for_each(|inst| {
+                emulator.step(inst);
+            });
+            print!("{}", emulator.dump());
+        }
+        _ => {
+            println!("Part 1: {}", part_1(&parsed));
+            match part_2(&parsed) {
+                Some(sum) => println!("Part 2: {}", sum),
+                None => println!("Part 2: sum overflows"),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -136,7 +393,7 @@ mem[8] = 0";
 
     #[test]
     fn test_part_1() {
-        let parsed = parse_input(TEST_INPUT_P1.as_bytes());
+        let parsed = parse_input(TEST_INPUT_P1.as_bytes(), WORD_WIDTH).unwrap();
         assert_eq!(part_1(&parsed), 165);
     }
 
@@ -147,7 +404,156 @@ mem[26] = 1";
 
     #[test]
     fn test_part_2() {
-        let parsed = parse_input(TEST_INPUT_P2.as_bytes());
-        assert_eq!(part_2(&parsed), 208);
+        let parsed = parse_input(TEST_INPUT_P2.as_bytes(), WORD_WIDTH).unwrap();
+        assert_eq!(part_2(&parsed), Some(208));
+    }
+
+    fn emulate(data: &[Instruction], decoder: Decoder) -> u64 {
+        let mut emulator = Emulator::new(decoder);
+        data.iter().for_each(|inst| {
+            emulator.step(inst);
+        });
+        emulator.memory().values().sum()
+    }
+
+    #[test]
+    fn test_emulator() {
+        let parsed = parse_input(TEST_INPUT_P1.as_bytes(), WORD_WIDTH).unwrap();
+        let mut emulator = Emulator::new(Decoder::V1);
+        assert_eq!(emulator.step(&parsed[0]), vec![]);
+        assert_eq!(
+            mask_to_string(emulator.mask()),
+            "XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X"
+        );
+        assert_eq!(
+            emulator.step(&parsed[1]),
+            vec![Write {
+                address: 8,
+                value: 73
+            }]
+        );
+        emulator.step(&parsed[2]);
+        assert_eq!(emulator.memory().len(), 2);
+        emulator.step(&parsed[3]);
+        assert_eq!(
+            emulator.dump(),
+            "000000007: 000000065\n000000008: 000000040\n"
+        );
+
+        let parsed = parse_input(TEST_INPUT_P2.as_bytes(), WORD_WIDTH).unwrap();
+        let mut emulator = Emulator::new(Decoder::V2);
+        let addresses = emulator
+            .step(&parsed[0])
+            .into_iter()
+            .chain(emulator.step(&parsed[1]))
+            .map(|w| w.address)
+            .collect_vec();
+        assert_eq!(addresses, vec![59, 58, 27, 26]);
+        parsed[2..].iter().for_each(|inst| {
+            emulator.step(inst);
+        });
+        assert_eq!(emulator.memory().len(), 10);
+        assert_eq!(emulate(&parsed, Decoder::V2), 208);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(
+            parse_input(b"mask = 1X0\nmem[7] = 7\n\nmem[6] = 1\n", 3).map(|p| p.len()),
+            Ok(3)
+        );
+        assert_eq!(
+            parse_input(b"mask = 1X0\nmask = 1X01", 3).err(),
+            Some(ParseError::MaskLength(2, 4))
+        );
+        assert_eq!(
+            parse_input(b"mask = 1X2", 3).err(),
+            Some(ParseError::MaskBit(1, '2'))
+        );
+        assert_eq!(
+            parse_input(b"mem[7] = 7\n\nmem[8] = 1", 3).err(),
+            Some(ParseError::AddressOverflow(3, 8))
+        );
+        assert_eq!(
+            parse_input(b"mem[7] = 8", 3).err(),
+            Some(ParseError::ValueOverflow(1, 8))
+        );
+        assert_eq!(
+            parse_input(b"mem[1] = 68719476736", WORD_WIDTH).err(),
+            Some(ParseError::ValueOverflow(1, 1 << 36))
+        );
+        assert_eq!(
+            parse_input(b"mem[x] = 1", WORD_WIDTH).err(),
+            Some(ParseError::Syntax(1, "mem[x] = 1".to_owned()))
+        );
+        assert_eq!(
+            parse_input(b"mask=0", 1).err(),
+            Some(ParseError::Syntax(1, "mask=0".to_owned()))
+        );
+        assert_eq!(parse_input(b"", 0).err(), Some(ParseError::Width(0)));
+        assert_eq!(parse_input(b"", 64).err(), Some(ParseError::Width(64)));
+
+        let max = (1u64 << MAX_WORD_WIDTH) - 1;
+        let program = format!("mask = {}\n", "X".repeat(63))
+            + &(0..3)
+                .map(|i| format!("mem[{}] = {}", max - i, max))
+                .join("\n");
+        let parsed = parse_input(program.as_bytes(), MAX_WORD_WIDTH).unwrap();
+        assert_eq!(part_2(&parsed), Some((max as u128) << 63));
+        assert_eq!(part_1(&parsed), 3 * max as u128);
+    }
+
+    #[test]
+    fn test_floating_memory() {
+        // Overlapping writes with up to 10 floating bits, against the
+        // emulator expanding them.
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let mut next = |n: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % n
+        };
+        let program = (0..40)
+            .map(|i| {
+                if i % 4 == 0 {
+                    let mask = (0..36)
+                        .map(|b| match (b < 10, next(10)) {
+                            (true, 0..=3) => 'X',
+                            (_, 0..=5) => '0',
+                            _ => '1',
+                        })
+                        .collect::<String>();
+                    format!("mask = {}", mask)
+                } else {
+                    format!("mem[{}] = {}", next(1 << 10), next(1000))
+                }
+            })
+            .join("\n");
+        let parsed = parse_input(program.as_bytes(), WORD_WIDTH).unwrap();
+        assert_eq!(part_2(&parsed), Some(emulate(&parsed, Decoder::V2) as u128));
+
+        let parsed = parse_input(
+            b"mask = 00XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 3
+mask = 0000XXXXXXXXXXXXXXXXXXXXXXXXXXXXXX10
+mem[1] = 5
+mask = 000000XXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[7] = 1",
+            WORD_WIDTH,
+        )
+        .unwrap();
+        assert_eq!(
+            part_2(&parsed),
+            Some((1 << 30) + 5 * ((1 << 30) - (1 << 28)) + 3 * ((1 << 34) - (2 << 30) + (1 << 28)))
+        );
+
+        let mut memory = FloatingMemory::default();
+        let pattern = Pattern {
+            fixed: 0,
+            floating: u64::MAX,
+        };
+        memory.write(pattern, u64::MAX);
+        assert_eq!(memory.sum(), None);
     }
 }